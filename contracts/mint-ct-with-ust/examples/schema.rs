@@ -0,0 +1,134 @@
+use std::env::current_dir;
+use std::fs::create_dir_all;
+
+use cosmwasm_schema::{export_schema_with_title, remove_schemas, schema_for};
+
+use mint_ct_with_ust::msg::{
+    AnchorCw20HookMsg, AnchorMsg, ClusterConfigResponse, ClusterExistsResponse,
+    ClusterStateResponse, ClusterTargetResponse, ClusterVolumeResponse, ClustersResponse,
+    Cw20HookMsg, ExecuteMsg, IncentivesMsg, InstantiateMsg, MintCapacityResponse,
+    MintHistoryResponse, PenaltyCreateResponse, PenaltyParamsResponse, PriceResponse, QueryMsg,
+    QueryMsgClusterFactory, QueryMsgNebula, QueryMsgOracleHub, QueryMsgPenalty,
+    ReferralStatsResponse, SimulateMintBatchResponse, SimulateMintExactResponse,
+    SimulateMintManyResponse, SimulateMintResponse, SimulateSwitchClusterResponse, StatusResponse,
+};
+use mint_ct_with_ust::state::{ClusterVolume, MintBucket, MintRecord, ReferralStats, State};
+
+fn main() {
+    let mut out_dir = current_dir().unwrap();
+    out_dir.push("schema");
+    create_dir_all(&out_dir).unwrap();
+    remove_schemas(&out_dir).unwrap();
+
+    // messages and responses of the contract
+    export_schema_with_title(&schema_for!(InstantiateMsg), &out_dir, "InstantiateMsg");
+    export_schema_with_title(&schema_for!(ExecuteMsg), &out_dir, "ExecuteMsg");
+    export_schema_with_title(&schema_for!(QueryMsg), &out_dir, "QueryMsg");
+    export_schema_with_title(&schema_for!(Cw20HookMsg), &out_dir, "Cw20HookMsg");
+    export_schema_with_title(
+        &schema_for!(SimulateMintResponse),
+        &out_dir,
+        "SimulateMintResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(SimulateMintBatchResponse),
+        &out_dir,
+        "SimulateMintBatchResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(SimulateMintExactResponse),
+        &out_dir,
+        "SimulateMintExactResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(SimulateMintManyResponse),
+        &out_dir,
+        "SimulateMintManyResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(SimulateSwitchClusterResponse),
+        &out_dir,
+        "SimulateSwitchClusterResponse",
+    );
+    export_schema_with_title(&schema_for!(ClustersResponse), &out_dir, "ClustersResponse");
+    export_schema_with_title(
+        &schema_for!(MintCapacityResponse),
+        &out_dir,
+        "MintCapacityResponse",
+    );
+    export_schema_with_title(&schema_for!(StatusResponse), &out_dir, "StatusResponse");
+    export_schema_with_title(
+        &schema_for!(ReferralStatsResponse),
+        &out_dir,
+        "ReferralStatsResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(MintHistoryResponse),
+        &out_dir,
+        "MintHistoryResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(ClusterVolumeResponse),
+        &out_dir,
+        "ClusterVolumeResponse",
+    );
+
+    // state
+    export_schema_with_title(&schema_for!(State), &out_dir, "State");
+    export_schema_with_title(&schema_for!(ReferralStats), &out_dir, "ReferralStats");
+    export_schema_with_title(&schema_for!(MintBucket), &out_dir, "MintBucket");
+    export_schema_with_title(&schema_for!(MintRecord), &out_dir, "MintRecord");
+    export_schema_with_title(&schema_for!(ClusterVolume), &out_dir, "ClusterVolume");
+
+    // messages and responses of the Nebula, Anchor and oracle hub contracts
+    export_schema_with_title(&schema_for!(QueryMsgNebula), &out_dir, "QueryMsgNebula");
+    export_schema_with_title(
+        &schema_for!(ClusterStateResponse),
+        &out_dir,
+        "ClusterStateResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(ClusterConfigResponse),
+        &out_dir,
+        "ClusterConfigResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(ClusterTargetResponse),
+        &out_dir,
+        "ClusterTargetResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(QueryMsgClusterFactory),
+        &out_dir,
+        "QueryMsgClusterFactory",
+    );
+    export_schema_with_title(
+        &schema_for!(ClusterExistsResponse),
+        &out_dir,
+        "ClusterExistsResponse",
+    );
+    export_schema_with_title(&schema_for!(IncentivesMsg), &out_dir, "IncentivesMsg");
+    export_schema_with_title(&schema_for!(QueryMsgPenalty), &out_dir, "QueryMsgPenalty");
+    export_schema_with_title(
+        &schema_for!(PenaltyCreateResponse),
+        &out_dir,
+        "PenaltyCreateResponse",
+    );
+    export_schema_with_title(
+        &schema_for!(PenaltyParamsResponse),
+        &out_dir,
+        "PenaltyParamsResponse",
+    );
+    export_schema_with_title(&schema_for!(AnchorMsg), &out_dir, "AnchorMsg");
+    export_schema_with_title(
+        &schema_for!(AnchorCw20HookMsg),
+        &out_dir,
+        "AnchorCw20HookMsg",
+    );
+    export_schema_with_title(
+        &schema_for!(QueryMsgOracleHub),
+        &out_dir,
+        "QueryMsgOracleHub",
+    );
+    export_schema_with_title(&schema_for!(PriceResponse), &out_dir, "PriceResponse");
+}
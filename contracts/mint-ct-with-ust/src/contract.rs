@@ -1,23 +1,40 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    attr, coin, to_binary, Addr, Attribute, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo,
-    QuerierWrapper, QueryRequest, Response, StdError, StdResult, Uint128, WasmMsg, WasmQuery,
+    attr, coin, from_binary, to_binary, Addr, BankMsg, Binary, CosmosMsg, Decimal, Deps, DepsMut,
+    Env, Event, MessageInfo, Order, QuerierWrapper, Response, StdError, StdResult, Storage, SubMsg,
+    Uint128, WasmMsg,
 };
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::str::FromStr;
 
 use cw2::set_contract_version;
-use cw20::Cw20ExecuteMsg;
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_storage_plus::{Bound, U64Key};
 
 use terra_cosmwasm::{create_swap_msg, TerraMsgWrapper, TerraQuerier};
 
+use crate::error::ContractError;
 use crate::msg::{
-    AnchorMsg, ClusterStateResponse, ExecuteMsg, IncentivesMsg, InstantiateMsg,
-    PenaltyCreateResponse, PriceResponse, QueryMsg, QueryMsgNebula, QueryMsgOracleHub,
-    QueryMsgPenalty, SimulateMintResponse,
+    AllocationMode, AnchorCw20HookMsg, AnchorMsg, ClusterMint, ClusterStateResponse,
+    ClusterVolumeResponse, ClustersResponse, Cw20HookMsg, ExecuteMsg, IncentivesMsg,
+    InstantiateMsg, MintCapacityResponse, MintHistoryResponse, MintQuote, MintReceipt,
+    MintRecordResponse, PenaltyCreateResponse, PriceResponse, QueryMsg, ReferralStatsResponse,
+    SimulateMintAsset, SimulateMintBatchResponse, SimulateMintBatchResult,
+    SimulateMintExactResponse, SimulateMintManyResponse, SimulateMintManyResult,
+    SimulateMintResponse, SimulateSwitchClusterResponse, StatusResponse, SwapLeg, SwapRoute,
+};
+use crate::querier::{ClusterContract, ClusterFactory, OracleHub, PenaltyContract};
+use crate::state::{
+    mint_history, ClusterVolume, MintBucket, MintRecord, ReferralStats, State, ALLOWED_CLUSTERS,
+    CLUSTER_TOKENS, CLUSTER_VOLUMES, MINT_COUNT, MINT_WINDOWS, REFERRAL_STATS, STATE,
 };
-use crate::state::{State, STATE};
 use astroport::asset::{Asset, AssetInfo};
-use astroport::pair::ExecuteMsg as AstroportExecuteMsg;
+use astroport::pair::{
+    Cw20HookMsg as AstroportCw20HookMsg, ExecuteMsg as AstroportExecuteMsg, SimulationResponse,
+};
 use astroport::querier::{query_balance, query_pair_info, query_token_balance, simulate};
 
 // version info for migration info
@@ -26,13 +43,112 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const DECIMAL_FRACTIONAL: Uint128 = Uint128::new(1_000_000_000u128); // 1*10**9
 
+/// Number of steps between the target and rebalance allocations tried by `AllocationMode::Optimize`
+const OPTIMIZE_STEPS: u128 = 4;
+
+/// UST amount simulated to estimate the price of a cluster token when searching a mint amount
+const SEARCH_PROBE_AMOUNT: Uint128 = Uint128::new(1_000_000u128);
+/// Maximum number of simulations when searching a mint amount
+const MAX_SEARCH_ITERATIONS: u32 = 32;
+
+/// Basis points denominator
+const BPS_DENOMINATOR: u128 = 10_000u128;
+/// Maximum protocol fee, 1%
+const MAX_PROTOCOL_FEE_BPS: u16 = 100;
+/// Maximum referral share the owner can allow, 1%
+const MAX_REFERRAL_BPS: u16 = 100;
+
+/// Default length of the per-cluster mint cap window, 1 day
+const DEFAULT_CAP_WINDOW_SECONDS: u64 = 86_400;
+/// Number of buckets the mints of a cap window are recorded in
+const CAP_WINDOW_BUCKETS: u64 = 24;
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
+/// ## Description
+/// A custom struct describing the point in time a mint is simulated at,
+/// along with the lookups shared between simulations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationContext {
+    /// Block height passed to the penalty contract
+    pub block_height: u64,
+    /// Block time in seconds, used to measure the age of oracle prices
+    pub block_time: u64,
+    /// Maximum age of oracle prices in seconds, unchecked when `None`
+    pub max_price_age: Option<u64>,
+    /// Astroport UST pairs already looked up, keyed by asset
+    pairs: RefCell<BTreeMap<String, Addr>>,
+}
+
+impl SimulationContext {
+    pub fn new(block_height: u64, block_time: u64, max_price_age: Option<u64>) -> Self {
+        SimulationContext {
+            block_height,
+            block_time,
+            max_price_age,
+            pairs: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// ## Description
+    /// Returns the current block without any price age limit.
+    pub fn from_env(env: &Env) -> Self {
+        SimulationContext::new(env.block.height, env.block.time.seconds(), None)
+    }
+
+    /// ## Description
+    /// Returns the Astroport pair of an asset against UST, querying the factory once per asset.
+    pub fn pair_address(
+        &self,
+        querier: &QuerierWrapper,
+        astroport_factory_address: &Addr,
+        asset_info: &AssetInfo,
+    ) -> StdResult<Addr> {
+        if let Some(pair) = self.pairs.borrow().get(&asset_info.to_string()) {
+            return Ok(pair.clone());
+        }
+
+        let pair = ust_pair(querier, astroport_factory_address.clone(), asset_info)?;
+        self.pairs
+            .borrow_mut()
+            .insert(asset_info.to_string(), pair.clone());
+
+        Ok(pair)
+    }
+
+    /// ## Description
+    /// Returns the Astroport pair of an asset against UST, or `None` when it has none. Any
+    /// other failure of the factory is returned.
+    pub fn try_pair_address(
+        &self,
+        querier: &QuerierWrapper,
+        astroport_factory_address: &Addr,
+        asset_info: &AssetInfo,
+    ) -> StdResult<Option<Addr>> {
+        match self.pair_address(querier, astroport_factory_address, asset_info) {
+            Ok(pair) => Ok(Some(pair)),
+            // the factory fails to load a missing pair, which reaches the querier as text
+            Err(StdError::NotFound { .. }) => Ok(None),
+            Err(StdError::GenericErr { msg }) if msg.contains("not found") => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
+    let protocol_fee_bps = msg.protocol_fee_bps.unwrap_or(0);
+    assert_fee(protocol_fee_bps, MAX_PROTOCOL_FEE_BPS)?;
+    let max_referral_bps = msg.max_referral_bps.unwrap_or(0);
+    assert_fee(max_referral_bps, MAX_REFERRAL_BPS)?;
+
     let state = State {
         incentive_contract: deps.api.addr_validate(msg.incentive_contract.as_ref())?,
         astroport_factory_address: deps
@@ -43,6 +159,25 @@ pub fn instantiate(
             .api
             .addr_validate(msg.anchor_market_contract.as_ref())?,
         oracle_hub_contract: deps.api.addr_validate(msg.oracle_hub_contract.as_ref())?,
+        fee_collector: match msg.fee_collector {
+            Some(fee_collector) => deps.api.addr_validate(fee_collector.as_ref())?,
+            None => info.sender.clone(),
+        },
+        guardian: match msg.guardian {
+            Some(guardian) => deps.api.addr_validate(guardian.as_ref())?,
+            None => info.sender.clone(),
+        },
+        paused: false,
+        cluster_factory: msg
+            .cluster_factory
+            .map(|cluster_factory| deps.api.addr_validate(cluster_factory.as_ref()))
+            .transpose()?,
+        max_ust_per_mint: None,
+        cluster_window_cap: None,
+        cap_window_seconds: DEFAULT_CAP_WINDOW_SECONDS,
+        max_price_deviation: msg.max_price_deviation,
+        protocol_fee_bps,
+        max_referral_bps,
         owner_address: info.sender,
     };
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
@@ -60,15 +195,138 @@ pub fn execute(
     env: Env,
     info: MessageInfo,
     msg: ExecuteMsg,
-) -> Result<Response<TerraMsgWrapper>, StdError> {
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    if !matches!(
+        msg,
+        ExecuteMsg::UpdateConfig { .. }
+            | ExecuteMsg::AddCluster { .. }
+            | ExecuteMsg::RemoveCluster { .. }
+            | ExecuteMsg::Pause {}
+            | ExecuteMsg::Unpause {}
+    ) && STATE.load(deps.storage)?.paused
+    {
+        return Err(ContractError::Paused {});
+    }
+    // the internal callbacks spend the balances of the contract and record mints, only the
+    // contract itself may send them
+    if matches!(
+        msg,
+        ExecuteMsg::_MintCT { .. }
+            | ExecuteMsg::_SendToUser { .. }
+            | ExecuteMsg::_MintMany { .. }
+            | ExecuteMsg::_SwitchSell { .. }
+            | ExecuteMsg::_SwitchBuy { .. }
+    ) && info.sender != env.contract.address
+    {
+        return Err(ContractError::Unauthorized {});
+    }
+
     match msg {
-        ExecuteMsg::MintCT { cluster_address } => mint_ct(deps, env, cluster_address, info.sender),
+        ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
+        ExecuteMsg::UpdateConfig {
+            protocol_fee_bps,
+            fee_collector,
+            max_referral_bps,
+            guardian,
+            cluster_factory,
+            max_ust_per_mint,
+            cluster_window_cap,
+            cap_window_seconds,
+            max_price_deviation,
+        } => update_config(
+            deps,
+            info,
+            protocol_fee_bps,
+            fee_collector,
+            max_referral_bps,
+            guardian,
+            cluster_factory,
+            max_ust_per_mint,
+            cluster_window_cap,
+            cap_window_seconds,
+            max_price_deviation,
+        ),
+        ExecuteMsg::AddCluster { cluster_address } => {
+            update_cluster(deps, info, cluster_address, true)
+        }
+        ExecuteMsg::RemoveCluster { cluster_address } => {
+            update_cluster(deps, info, cluster_address, false)
+        }
+        ExecuteMsg::Pause {} => set_paused(deps, info, true),
+        ExecuteMsg::Unpause {} => set_paused(deps, info, false),
+        ExecuteMsg::MintCT {
+            cluster_address,
+            allocation,
+            referrer,
+            referral_bps,
+            quote,
+        } => {
+            let ust_amt = info
+                .funds
+                .iter()
+                .find(|c| c.denom == "uusd")
+                .map(|c| c.amount)
+                .unwrap_or_default();
+            let in_kind = info
+                .funds
+                .iter()
+                .filter(|c| c.denom != "uusd")
+                .map(|c| Asset {
+                    info: AssetInfo::NativeToken {
+                        denom: c.denom.clone(),
+                    },
+                    amount: c.amount,
+                })
+                .collect();
+            mint_ct(
+                deps,
+                env,
+                cluster_address,
+                info.sender,
+                ust_amt,
+                allocation.unwrap_or(AllocationMode::Target),
+                referrer,
+                referral_bps,
+                in_kind,
+                quote,
+            )
+        }
+        ExecuteMsg::MintCTExact {
+            cluster_address,
+            cluster_token_amount,
+            allocation,
+            tolerance,
+        } => mint_ct_exact(
+            deps,
+            env,
+            info,
+            cluster_address,
+            cluster_token_amount,
+            allocation.unwrap_or(AllocationMode::Target),
+            tolerance.unwrap_or_else(|| Decimal::permille(1)),
+        ),
+        ExecuteMsg::MintMany {
+            allocations,
+            min_outputs,
+            allocation,
+        } => mint_many(
+            deps,
+            env,
+            info,
+            allocations,
+            min_outputs,
+            allocation.unwrap_or(AllocationMode::Target),
+        ),
         ExecuteMsg::_MintCT {
             cluster_address,
             natives,
             tokens,
             cluster_token,
             user,
+            min_tokens,
+            min_assets,
+            balances_before,
+            receipt,
         } => _mint_ct(
             deps,
             env,
@@ -77,48 +335,477 @@ pub fn execute(
             tokens,
             cluster_token,
             user,
+            min_tokens,
+            min_assets,
+            balances_before,
+            receipt,
         ),
         ExecuteMsg::_SendToUser {
+            cluster_address,
             cluster_token,
             user,
-        } => _send_to_user(deps, env, cluster_token, user),
+            receipt,
+        } => _send_to_user(deps, env, cluster_address, cluster_token, user, receipt),
+        ExecuteMsg::_MintMany {
+            user,
+            swaps,
+            balances_before,
+            mints,
+        } => _mint_many(deps, env, user, swaps, balances_before, mints),
+        ExecuteMsg::_SwitchSell {
+            from_cluster,
+            to_cluster,
+            user,
+            min_out,
+        } => _switch_sell(deps, env, from_cluster, to_cluster, user, min_out),
+        ExecuteMsg::_SwitchBuy {
+            to_cluster,
+            user,
+            min_out,
+            deficits,
+            value,
+        } => _switch_buy(deps, env, to_cluster, user, min_out, deficits, value),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
     match msg {
         QueryMsg::SimulateMint {
             cluster_address,
             ust_amount,
-        } => to_binary(&simulate_mint(deps, env, cluster_address, ust_amount)?),
+            allocation,
+            block_height,
+            max_price_age,
+            referral_bps,
+            in_kind,
+        } => Ok(to_binary(&simulate_mint(
+            deps,
+            env,
+            cluster_address,
+            ust_amount,
+            allocation.unwrap_or(AllocationMode::Target),
+            block_height,
+            max_price_age,
+            referral_bps,
+            in_kind.unwrap_or_default(),
+        )?)?),
+        QueryMsg::Clusters { start_after, limit } => {
+            Ok(to_binary(&query_clusters(deps, start_after, limit)?)?)
+        }
+        QueryMsg::MintCapacity { cluster_address } => Ok(to_binary(&query_mint_capacity(
+            deps,
+            env,
+            cluster_address,
+        )?)?),
+        QueryMsg::Status {} => Ok(to_binary(&query_status(deps)?)?),
+        QueryMsg::ReferralStats { address } => {
+            Ok(to_binary(&query_referral_stats(deps, address)?)?)
+        }
+        QueryMsg::MintHistory {
+            user,
+            cluster,
+            start_after,
+            limit,
+        } => Ok(to_binary(&query_mint_history(
+            deps,
+            user,
+            cluster,
+            start_after,
+            limit,
+        )?)?),
+        QueryMsg::ClusterVolume { cluster_address } => {
+            Ok(to_binary(&query_cluster_volume(deps, cluster_address)?)?)
+        }
+        QueryMsg::SimulateMintBatch {
+            requests,
+            allocation,
+        } => Ok(to_binary(&simulate_mint_batch(
+            deps,
+            env,
+            requests,
+            allocation.unwrap_or(AllocationMode::Target),
+        )?)?),
+        QueryMsg::SimulateMintMany {
+            allocations,
+            ust_amount,
+            allocation,
+        } => Ok(to_binary(&simulate_mint_many(
+            deps,
+            env,
+            allocations,
+            ust_amount,
+            allocation.unwrap_or(AllocationMode::Target),
+        )?)?),
+        QueryMsg::SimulateSwitchCluster {
+            from_cluster,
+            to_cluster,
+            amount,
+        } => Ok(to_binary(&simulate_switch_cluster(
+            deps,
+            env,
+            from_cluster,
+            to_cluster,
+            amount,
+        )?)?),
+        QueryMsg::SimulateMintExact {
+            cluster_address,
+            cluster_token_amount,
+            allocation,
+            tolerance,
+        } => Ok(to_binary(&simulate_mint_exact(
+            deps,
+            env,
+            cluster_address,
+            cluster_token_amount,
+            allocation.unwrap_or(AllocationMode::Target),
+            tolerance.unwrap_or_else(|| Decimal::permille(1)),
+        )?)?),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    protocol_fee_bps: Option<u16>,
+    fee_collector: Option<String>,
+    max_referral_bps: Option<u16>,
+    guardian: Option<String>,
+    cluster_factory: Option<String>,
+    max_ust_per_mint: Option<Uint128>,
+    cluster_window_cap: Option<Uint128>,
+    cap_window_seconds: Option<u64>,
+    max_price_deviation: Option<Decimal>,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let mut state = STATE.load(deps.storage)?;
+    if info.sender != state.owner_address {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if let Some(protocol_fee_bps) = protocol_fee_bps {
+        assert_fee(protocol_fee_bps, MAX_PROTOCOL_FEE_BPS)?;
+        state.protocol_fee_bps = protocol_fee_bps;
+    }
+    if let Some(fee_collector) = fee_collector {
+        state.fee_collector = deps.api.addr_validate(fee_collector.as_ref())?;
+    }
+    if let Some(max_referral_bps) = max_referral_bps {
+        assert_fee(max_referral_bps, MAX_REFERRAL_BPS)?;
+        state.max_referral_bps = max_referral_bps;
+    }
+    if let Some(guardian) = guardian {
+        state.guardian = deps.api.addr_validate(guardian.as_ref())?;
+    }
+    if let Some(cluster_factory) = cluster_factory {
+        state.cluster_factory = Some(deps.api.addr_validate(cluster_factory.as_ref())?);
+    }
+    if let Some(max_ust_per_mint) = max_ust_per_mint {
+        state.max_ust_per_mint = Some(max_ust_per_mint).filter(|cap| !cap.is_zero());
+    }
+    if let Some(cluster_window_cap) = cluster_window_cap {
+        state.cluster_window_cap = Some(cluster_window_cap).filter(|cap| !cap.is_zero());
+    }
+    if let Some(cap_window_seconds) = cap_window_seconds {
+        if cap_window_seconds == 0 {
+            return Err(StdError::generic_err("cap_window_seconds must be positive").into());
+        }
+        state.cap_window_seconds = cap_window_seconds;
+    }
+    if let Some(max_price_deviation) = max_price_deviation {
+        state.max_price_deviation = Some(max_price_deviation).filter(|max| !max.is_zero());
+    }
+    STATE.save(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attribute("method", "update_config")
+        .add_attribute("protocol_fee_bps", state.protocol_fee_bps.to_string())
+        .add_attribute("fee_collector", state.fee_collector)
+        .add_attribute("max_referral_bps", state.max_referral_bps.to_string())
+        .add_attribute("guardian", state.guardian))
+}
+
+/// ## Description
+/// Adds a cluster to or removes it from the allowlist, callable by the owner.
+pub fn update_cluster(
+    deps: DepsMut,
+    info: MessageInfo,
+    cluster_address: String,
+    allowed: bool,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if info.sender != state.owner_address {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let cluster = deps.api.addr_validate(cluster_address.as_ref())?;
+    if allowed {
+        let cluster_token = deps.api.addr_validate(
+            ClusterContract(cluster.clone())
+                .state(&deps.querier)?
+                .cluster_token
+                .as_ref(),
+        )?;
+        ALLOWED_CLUSTERS.save(deps.storage, &cluster, &true)?;
+        CLUSTER_TOKENS.save(deps.storage, &cluster_token, &cluster)?;
+    } else {
+        ALLOWED_CLUSTERS.remove(deps.storage, &cluster);
+        // the cluster may no longer answer queries, so its token is found by scanning
+        let cluster_tokens = CLUSTER_TOKENS
+            .range(deps.storage, None, None, Order::Ascending)
+            .filter_map(|item| match item {
+                Ok((key, value)) if value == cluster => Some(Ok(key)),
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<StdResult<Vec<Vec<u8>>>>()?;
+        for key in cluster_tokens {
+            let cluster_token = Addr::unchecked(String::from_utf8(key).map_err(StdError::from)?);
+            CLUSTER_TOKENS.remove(deps.storage, &cluster_token);
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute(
+            "method",
+            if allowed {
+                "add_cluster"
+            } else {
+                "remove_cluster"
+            },
+        )
+        .add_attribute("cluster_address", cluster))
+}
+
+/// ## Description
+/// Pauses or resumes the execute messages, callable by the owner and the guardian.
+pub fn set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let mut state = STATE.load(deps.storage)?;
+    if info.sender != state.owner_address && info.sender != state.guardian {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    state.paused = paused;
+    STATE.save(deps.storage, &state)?;
+
+    Ok(Response::new()
+        .add_attribute("method", if paused { "pause" } else { "unpause" })
+        .add_attribute("sender", info.sender))
+}
+
+fn assert_fee(fee_bps: u16, max_bps: u16) -> Result<(), ContractError> {
+    if fee_bps > max_bps {
+        return Err(ContractError::FeeTooHigh { max_bps });
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Records `ust_amt` against the mint caps of a cluster, failing when a cap is exceeded.
+pub fn consume_mint_capacity(
+    storage: &mut dyn Storage,
+    state: &State,
+    cluster: &Addr,
+    block_time: u64,
+    ust_amt: Uint128,
+) -> Result<(), ContractError> {
+    assert_mint_cap(state, ust_amt)?;
+    consume_window_capacity(storage, state, cluster, block_time, ust_amt)
+}
+
+/// ## Description
+/// Fails when the UST of a single mint exceeds `max_ust_per_mint`.
+fn assert_mint_cap(state: &State, ust_amt: Uint128) -> Result<(), ContractError> {
+    if let Some(max_ust_per_mint) = state.max_ust_per_mint {
+        if ust_amt > max_ust_per_mint {
+            return Err(ContractError::MintCapExceeded {
+                max: max_ust_per_mint,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Records `ust_amt` in the cap window of a cluster, failing when the window cap is exceeded.
+fn consume_window_capacity(
+    storage: &mut dyn Storage,
+    state: &State,
+    cluster: &Addr,
+    block_time: u64,
+    ust_amt: Uint128,
+) -> Result<(), ContractError> {
+    if let Some(cluster_window_cap) = state.cluster_window_cap {
+        let (used, expired) = window_usage(storage, state, cluster, block_time)?;
+        if used + ust_amt > cluster_window_cap {
+            return Err(ContractError::WindowCapExceeded {
+                cluster: cluster.to_string(),
+                remaining: cluster_window_cap.saturating_sub(used),
+            });
+        }
+
+        for bucket_start in expired {
+            MINT_WINDOWS.remove(storage, (cluster, U64Key::new(bucket_start)));
+        }
+        let bucket_seconds = cap_bucket_seconds(state);
+        let bucket_start = block_time - block_time % bucket_seconds;
+        let bucket = MINT_WINDOWS
+            .may_load(storage, (cluster, U64Key::new(bucket_start)))?
+            .unwrap_or_default();
+        // a bucket opened before the window changed keeps the longer of both lengths, so
+        // none of its mints leaves the window early
+        MINT_WINDOWS.save(
+            storage,
+            (cluster, U64Key::new(bucket_start)),
+            &MintBucket {
+                seconds: std::cmp::max(bucket.seconds, bucket_seconds),
+                amount: bucket.amount.checked_add(ust_amt).map_err(StdError::from)?,
+            },
+        )?;
     }
+
+    Ok(())
+}
+
+/// ## Description
+/// Returns the length of the buckets the mints of a cap window are recorded in.
+fn cap_bucket_seconds(state: &State) -> u64 {
+    std::cmp::max(state.cap_window_seconds / CAP_WINDOW_BUCKETS, 1)
+}
+
+/// ## Description
+/// Returns the UST minted into a cluster over the cap window ending at `block_time`, along
+/// with the start of the buckets that ended before the window. A bucket overlapping the
+/// start of the window counts in full, so no window of mints ever exceeds the cap. Each
+/// bucket is measured with the length it was recorded with.
+fn window_usage(
+    storage: &dyn Storage,
+    state: &State,
+    cluster: &Addr,
+    block_time: u64,
+) -> StdResult<(Uint128, Vec<u64>)> {
+    let window_start = block_time.saturating_sub(state.cap_window_seconds);
+
+    let mut used = Uint128::zero();
+    let mut expired = vec![];
+    for item in MINT_WINDOWS
+        .prefix(cluster)
+        .range(storage, None, None, Order::Ascending)
+    {
+        let (key, bucket) = item?;
+        let bucket_start = u64::from_be_bytes(
+            key.as_slice()
+                .try_into()
+                .map_err(|_| StdError::generic_err("invalid mint window key"))?,
+        );
+        if bucket_start + bucket.seconds <= window_start + 1 {
+            expired.push(bucket_start);
+        } else {
+            used = used.checked_add(bucket.amount)?;
+        }
+    }
+
+    Ok((used, expired))
+}
+
+/// ## Description
+/// Returns the protocol fee taken from `ust_amt`.
+pub fn compute_protocol_fee(state: &State, ust_amt: Uint128) -> Uint128 {
+    ust_amt.multiply_ratio(state.protocol_fee_bps, BPS_DENOMINATOR)
 }
 
 pub fn _send_to_user(
     deps: DepsMut,
     env: Env,
+    cluster_address: String,
     cluster_token: String,
     user: String,
-) -> StdResult<Response<TerraMsgWrapper>> {
+    receipt: MintReceipt,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    if let Some(cluster_factory) = state.cluster_factory {
+        verify_cluster_token(
+            deps.as_ref(),
+            &cluster_factory,
+            &cluster_address,
+            &cluster_token,
+        )?;
+    }
+
     let amount = query_token_balance(
         &deps.querier,
         deps.api.addr_validate(cluster_token.as_ref())?,
-        env.contract.address,
+        env.contract.address.clone(),
     )?;
 
-    Ok(
-        Response::new().add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+    let mint_id = record_mint(
+        deps.storage,
+        MintRecord {
+            user: deps.api.addr_validate(user.as_ref())?,
+            cluster: deps.api.addr_validate(cluster_address.as_ref())?,
+            ust_in: receipt.ust_in,
+            cluster_tokens_out: amount,
+            block_height: env.block.height,
+            block_time: env.block.time.seconds(),
+        },
+    )?;
+
+    let event = Event::new("mint_complete").add_attributes(vec![
+        attr("mint_id", mint_id.to_string()),
+        attr("user", user.clone()),
+        attr("cluster", cluster_address),
+        attr("cluster_token", cluster_token.clone()),
+        attr("cluster_tokens_out", amount),
+        attr("ust_in", receipt.ust_in),
+        attr("protocol_fee", receipt.protocol_fee),
+        attr("referral_fee", receipt.referral_fee),
+        attr("refund", receipt.refund),
+    ]);
+
+    Ok(Response::new()
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr: cluster_token,
             msg: to_binary(&Cw20ExecuteMsg::Transfer {
                 recipient: user,
                 amount,
             })?,
             funds: vec![],
-        })),
-    )
+        }))
+        .add_event(event))
+}
+
+/// ## Description
+/// Stores a completed mint in the history, adds it to the totals of its cluster and
+/// returns its id.
+fn record_mint(storage: &mut dyn Storage, record: MintRecord) -> StdResult<u64> {
+    let id = MINT_COUNT.may_load(storage)?.unwrap_or_default();
+    MINT_COUNT.save(storage, &(id + 1))?;
+
+    CLUSTER_VOLUMES.update(
+        storage,
+        &record.cluster,
+        |volume| -> StdResult<ClusterVolume> {
+            let mut volume = volume.unwrap_or_default();
+            volume.ust_in = volume.ust_in.checked_add(record.ust_in)?;
+            volume.cluster_tokens_out = volume
+                .cluster_tokens_out
+                .checked_add(record.cluster_tokens_out)?;
+            volume.mints += 1;
+            Ok(volume)
+        },
+    )?;
+    mint_history().save(storage, U64Key::new(id), &record)?;
+
+    Ok(id)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn _mint_ct(
     deps: DepsMut,
     env: Env,
@@ -127,329 +814,2632 @@ pub fn _mint_ct(
     tokens: Vec<String>,
     cluster_token: String,
     user: String,
-) -> StdResult<Response<TerraMsgWrapper>> {
+    min_tokens: Option<Uint128>,
+    min_assets: Option<Vec<Asset>>,
+    balances_before: Vec<Asset>,
+    receipt: MintReceipt,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
     let state = STATE.load(deps.storage)?;
     let _validated_user = deps.api.addr_validate(user.as_str());
 
-    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
-    let mut funds = vec![];
-    let mut assets = vec![];
-    let mut attrs: Vec<Attribute> = vec![];
-
+    // a balance listed twice would be provided twice
+    let mut infos: Vec<AssetInfo> = vec![];
     for native in natives {
-        let amount = query_balance(&deps.querier, env.contract.address.clone(), native.clone())?;
-        funds.push(coin(amount.u128(), native.clone()));
-        assets.push(Asset {
-            info: AssetInfo::NativeToken {
-                denom: native.clone(),
-            },
-            amount,
-        });
-
-        attrs.push(attr("denom", native));
-        attrs.push(attr("balance", amount));
+        let info = AssetInfo::NativeToken { denom: native };
+        if !infos.contains(&info) {
+            infos.push(info);
+        }
     }
-
     for token in tokens {
-        let contract_addr = deps.api.addr_validate(token.as_ref())?;
-        let amount = query_token_balance(
-            &deps.querier,
-            contract_addr.clone(),
-            env.contract.address.clone(),
-        )?;
-        msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: contract_addr.to_string(),
-            msg: to_binary(&Cw20ExecuteMsg::IncreaseAllowance {
-                spender: state.incentive_contract.to_string(),
-                amount,
-                expires: None,
-            })?,
-            funds: vec![],
-        }));
-        assets.push(Asset {
-            info: AssetInfo::Token { contract_addr },
-            amount,
-        });
-
-        attrs.push(attr("token", token));
-        attrs.push(attr("balance", amount));
+        let info = AssetInfo::Token {
+            contract_addr: deps.api.addr_validate(token.as_ref())?,
+        };
+        if !infos.contains(&info) {
+            infos.push(info);
+        }
+    }
+    // the assets bought for the mint are the whole balances of the contract
+    let assets = query_balances(deps.as_ref(), &env.contract.address, infos)?;
+    if let Some(min_assets) = min_assets {
+        let received: Vec<Asset> = assets
+            .iter()
+            .map(|asset| Asset {
+                info: asset.info.clone(),
+                amount: asset
+                    .amount
+                    .saturating_sub(amount_of(&balances_before, &asset.info)),
+            })
+            .collect();
+        assert_min_assets(&received, &min_assets)?;
     }
 
-    funds.sort_by(|c1, c2| c1.denom.cmp(&c2.denom));
-
-    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-        contract_addr: state.incentive_contract.to_string(),
-        msg: to_binary(&IncentivesMsg::IncentivesCreate {
-            cluster_contract: cluster_address.clone(),
-            asset_amounts: assets,
-            min_tokens: None,
-        })?,
-        funds,
-    }));
+    let events = swap_events(&receipt.swaps, &balances_before, &assets);
+    let mut msgs = create_msgs(&state, &cluster_address, assets, min_tokens)?;
 
     msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
         contract_addr: env.contract.address.to_string(),
         msg: to_binary(&ExecuteMsg::_SendToUser {
+            cluster_address,
             cluster_token,
             user,
+            receipt,
         })?,
         funds: vec![],
     }));
 
-    Ok(Response::new().add_messages(msgs).add_attributes(attrs))
-}
-
-pub fn swap_to_ust(
-    querier: &QuerierWrapper,
-    offer_asset: Asset,
-    astroport_factory_address: Addr,
-) -> Result<CosmosMsg<TerraMsgWrapper>, StdError> {
-    let pair_contract = query_pair_info(
-        querier,
-        astroport_factory_address.clone(),
-        &[
-            offer_asset.info.clone(),
-            AssetInfo::NativeToken {
-                denom: "uusd".to_string(),
-            },
-        ],
-    )?
-    .contract_addr
-    .to_string();
-
-    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
-        contract_addr: pair_contract,
-        msg: to_binary(&AstroportExecuteMsg::Swap {
-            offer_asset: Asset {
-                info: AssetInfo::NativeToken {
-                    denom: "uusd".to_string(),
-                },
-                amount: offer_asset.amount.clone(),
-            },
-            belief_price: None,
-            max_spread: None,
-            to: None,
-        })?,
-        funds: vec![coin(offer_asset.amount.u128(), "uusd")],
-    }))
+    Ok(Response::new().add_messages(msgs).add_events(events))
 }
 
-pub fn mint_ct(
+/// ## Description
+/// Mints the cluster tokens of each `ClusterMint` with its share of the assets bought by
+/// `MintMany`, split between the clusters pro rata of the UST they spent on each asset.
+pub fn _mint_many(
     deps: DepsMut,
     env: Env,
-    cluster_address: String,
-    user: Addr,
-) -> StdResult<Response<TerraMsgWrapper>> {
+    user: String,
+    swaps: Vec<SwapLeg>,
+    balances_before: Vec<Asset>,
+    mints: Vec<ClusterMint>,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
     let state = STATE.load(deps.storage)?;
-    let validated_cluster_address = deps.api.addr_validate(cluster_address.as_ref())?;
-    let cluster_state = get_cluster_state(deps.as_ref(), &validated_cluster_address)?;
-    let total_target_weight: Uint128 = cluster_state.target.clone().iter().map(|x| x.amount).sum();
-    let ust_amt = query_balance(
-        &deps.querier,
-        env.contract.address.clone(),
-        "uusd".to_string(),
+    deps.api.addr_validate(user.as_str())?;
+
+    let baskets: Vec<Vec<Asset>> = mints.iter().map(|mint| mint.assets.clone()).collect();
+    let bought = query_balances(
+        deps.as_ref(),
+        &env.contract.address,
+        aggregate_assets(&baskets)?
+            .into_iter()
+            .map(|asset| asset.info)
+            .collect(),
     )?;
+    let events = swap_events(&swaps, &balances_before, &bought);
 
-    let mut natives: Vec<String> = vec![];
-    let mut tokens: Vec<String> = vec![];
-    let mut attrs: Vec<Attribute> = vec![];
     let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
-
-    for asset in cluster_state.target.clone() {
-        let asset_ratio = ust_amt * asset.amount / total_target_weight;
-
-        match asset.info.clone() {
-            AssetInfo::NativeToken { denom } => {
-                natives.push(denom.clone());
-                if denom == "uusd" {
-                    continue;
+    for (mint, assets) in mints.into_iter().zip(split_assets(&baskets, &bought)?) {
+        msgs.extend(create_msgs(
+            &state,
+            &mint.cluster_address,
+            assets,
+            mint.min_tokens,
+        )?);
+        msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_binary(&ExecuteMsg::_SendToUser {
+                cluster_address: mint.cluster_address,
+                cluster_token: mint.cluster_token,
+                user: user.clone(),
+                receipt: mint.receipt,
+            })?,
+            funds: vec![],
+        }));
+    }
+
+    Ok(Response::new().add_messages(msgs).add_events(events))
+}
+
+/// ## Description
+/// Sells the assets redeemed by `SwitchCluster` held above the target of `to_cluster`,
+/// keeping the rest for the mint.
+pub fn _switch_sell(
+    deps: DepsMut,
+    env: Env,
+    from_cluster: String,
+    to_cluster: String,
+    user: String,
+    min_out: Option<Uint128>,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    deps.api.addr_validate(user.as_str())?;
+    let from_state = load_cluster_state(deps.as_ref(), &from_cluster)?;
+    let to_state = load_cluster_state(deps.as_ref(), &to_cluster)?;
+
+    // the redeemed assets are the whole balances of the contract
+    let redeemed = query_balances(
+        deps.as_ref(),
+        &env.contract.address,
+        from_state
+            .target
+            .iter()
+            .map(|asset| asset.info.clone())
+            .collect(),
+    )?;
+    let plan = plan_switch(&redeemed, &from_state, &to_state)?;
+
+    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
+    let mut events: Vec<Event> = vec![];
+    for asset in plan.sells {
+        let (msg, venue) = sell_asset(deps.as_ref(), &state, &asset)?;
+        events.push(Event::new("sell").add_attributes(vec![
+            attr("asset", asset.info.to_string()),
+            attr("venue", venue),
+            attr("amount_in", asset.amount),
+        ]));
+        msgs.extend(msg);
+    }
+
+    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::_SwitchBuy {
+            to_cluster,
+            user,
+            min_out,
+            deficits: plan.deficits,
+            value: plan.value,
+        })?,
+        funds: vec![],
+    }));
+
+    Ok(Response::new().add_messages(msgs).add_events(events))
+}
+
+/// ## Description
+/// Spends the UST of the sales on the target assets of `to_cluster` held below target,
+/// pro rata of their `deficits`, then mints its cluster token to `user`. The `value` of the
+/// redeemed assets counts against the mint caps of `to_cluster`, while the protocol fee is
+/// only charged on the UST of the sales.
+#[allow(clippy::too_many_arguments)]
+pub fn _switch_buy(
+    deps: DepsMut,
+    env: Env,
+    to_cluster: String,
+    user: String,
+    min_out: Option<Uint128>,
+    deficits: Vec<Asset>,
+    value: Uint128,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    deps.api.addr_validate(user.as_str())?;
+    let to_state = load_cluster_state(deps.as_ref(), &to_cluster)?;
+    consume_mint_capacity(
+        deps.storage,
+        &state,
+        &deps.api.addr_validate(to_cluster.as_ref())?,
+        env.block.time.seconds(),
+        value,
+    )?;
+    let ust_amt = query_balance(
+        &deps.querier,
+        env.contract.address.clone(),
+        "uusd".to_string(),
+    )?;
+
+    let ctx = SimulationContext::from_env(&env);
+    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
+    let mut receipt = MintReceipt {
+        ust_in: value,
+        ..MintReceipt::default()
+    };
+
+    let protocol_fee = compute_protocol_fee(&state, ust_amt);
+    receipt.protocol_fee = protocol_fee;
+    if !protocol_fee.is_zero() {
+        msgs.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: state.fee_collector.to_string(),
+            amount: vec![coin(protocol_fee.u128(), "uusd")],
+        }));
+    }
+    let ust_amt = ust_amt - protocol_fee;
+
+    let weights: Vec<Uint128> = deficits.iter().map(|asset| asset.amount).collect();
+    if weights.iter().all(|weight| weight.is_zero()) {
+        // nothing is missing, the UST of the sales goes back to the user unless the
+        // cluster holds UST itself
+        let holds_ust = to_state.target.iter().any(|asset| {
+            asset.info
+                == AssetInfo::NativeToken {
+                    denom: "uusd".to_string(),
+                }
+        });
+        if !holds_ust && !ust_amt.is_zero() {
+            receipt.refund = ust_amt;
+            msgs.push(CosmosMsg::Bank(BankMsg::Send {
+                to_address: user.clone(),
+                amount: vec![coin(ust_amt.u128(), "uusd")],
+            }));
+        }
+    } else {
+        for (asset, ust_amount) in deficits.iter().zip(split_by_weights(ust_amt, &weights)?) {
+            if ust_amount.is_zero() {
+                continue;
+            }
+
+            let (msg, venue) = buy_asset(deps.as_ref(), &ctx, &state, &asset.info, ust_amount)?;
+            receipt.swaps.push(swap_leg(&asset.info, venue, ust_amount));
+            msgs.extend(msg);
+        }
+    }
+
+    // the redeemed assets kept for the new cluster are already held
+    let balances_before = balances_before_swaps(
+        deps.as_ref(),
+        &env.contract.address,
+        to_state.target.iter().map(|asset| &asset.info),
+    )?;
+    let mut natives: Vec<String> = vec![];
+    let mut tokens: Vec<String> = vec![];
+    for asset in to_state.target.iter() {
+        match &asset.info {
+            AssetInfo::NativeToken { denom } => natives.push(denom.clone()),
+            AssetInfo::Token { contract_addr } => tokens.push(contract_addr.to_string()),
+        }
+    }
+
+    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::_MintCT {
+            cluster_address: to_cluster,
+            natives,
+            tokens,
+            cluster_token: to_state.cluster_token,
+            user,
+            min_tokens: min_out,
+            min_assets: None,
+            balances_before,
+            receipt,
+        })?,
+        funds: vec![],
+    }));
+
+    Ok(Response::new().add_messages(msgs))
+}
+
+/// ## Description
+/// Returns the balances of `account` in each asset.
+fn query_balances(deps: Deps, account: &Addr, infos: Vec<AssetInfo>) -> StdResult<Vec<Asset>> {
+    infos
+        .into_iter()
+        .map(|info| {
+            let amount = match &info {
+                AssetInfo::NativeToken { denom } => {
+                    query_balance(&deps.querier, account.clone(), denom.clone())?
+                }
+                AssetInfo::Token { contract_addr } => {
+                    query_token_balance(&deps.querier, contract_addr.clone(), account.clone())?
+                }
+            };
+
+            Ok(Asset { info, amount })
+        })
+        .collect()
+}
+
+/// ## Description
+/// Returns the balances of `account` in each asset but UST, the amounts a mint measures what
+/// it bought against.
+fn balances_before_swaps<'a>(
+    deps: Deps,
+    account: &Addr,
+    infos: impl Iterator<Item = &'a AssetInfo>,
+) -> StdResult<Vec<Asset>> {
+    let mut unique: Vec<AssetInfo> = vec![];
+    for info in infos {
+        if *info
+            != (AssetInfo::NativeToken {
+                denom: "uusd".to_string(),
+            })
+            && !unique.contains(info)
+        {
+            unique.push(info.clone());
+        }
+    }
+
+    query_balances(deps, account, unique)
+}
+
+/// ## Description
+/// Returns a `swap` event per leg, reporting the amount of the asset in `bought` beyond
+/// `balances_before`.
+fn swap_events(swaps: &[SwapLeg], balances_before: &[Asset], bought: &[Asset]) -> Vec<Event> {
+    swaps
+        .iter()
+        .map(|leg| {
+            let amount_out = amount_of(bought, &leg.asset)
+                .saturating_sub(amount_of(balances_before, &leg.asset));
+
+            Event::new("swap").add_attributes(vec![
+                attr("asset", leg.asset.to_string()),
+                attr("venue", leg.venue.clone()),
+                attr("ust_in", leg.ust_in),
+                attr("amount_out", amount_out),
+            ])
+        })
+        .collect()
+}
+
+/// ## Description
+/// Returns the messages providing `assets` to the incentives contract to create cluster
+/// tokens of `cluster_address`.
+fn create_msgs(
+    state: &State,
+    cluster_address: &str,
+    assets: Vec<Asset>,
+    min_tokens: Option<Uint128>,
+) -> StdResult<Vec<CosmosMsg<TerraMsgWrapper>>> {
+    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
+    let mut funds = vec![];
+    for asset in assets.iter() {
+        match &asset.info {
+            AssetInfo::NativeToken { denom } => {
+                if !asset.amount.is_zero() {
+                    funds.push(coin(asset.amount.u128(), denom.clone()));
                 }
-                attrs.push(attr("swap_ust_to_native_", denom.clone()));
-                attrs.push(attr("amount", asset_ratio.clone()));
+            }
+            AssetInfo::Token { contract_addr } => {
+                msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: contract_addr.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                        spender: state.incentive_contract.to_string(),
+                        amount: asset.amount,
+                        expires: None,
+                    })?,
+                    funds: vec![],
+                }));
+            }
+        }
+    }
+    funds.sort_by(|c1, c2| c1.denom.cmp(&c2.denom));
+
+    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: state.incentive_contract.to_string(),
+        msg: to_binary(&IncentivesMsg::IncentivesCreate {
+            cluster_contract: cluster_address.to_string(),
+            asset_amounts: assets,
+            min_tokens,
+        })?,
+        funds,
+    }));
+
+    Ok(msgs)
+}
+
+/// ## Description
+/// Returns the Astroport pair of an asset against UST.
+fn ust_pair(
+    querier: &QuerierWrapper,
+    astroport_factory_address: Addr,
+    asset_info: &AssetInfo,
+) -> StdResult<Addr> {
+    Ok(query_pair_info(
+        querier,
+        astroport_factory_address,
+        &[
+            asset_info.clone(),
+            AssetInfo::NativeToken {
+                denom: "uusd".to_string(),
+            },
+        ],
+    )?
+    .contract_addr)
+}
+
+pub fn swap_to_ust(
+    querier: &QuerierWrapper,
+    offer_asset: Asset,
+    astroport_factory_address: Addr,
+) -> Result<CosmosMsg<TerraMsgWrapper>, StdError> {
+    let pair_contract =
+        ust_pair(querier, astroport_factory_address, &offer_asset.info)?.to_string();
+
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: pair_contract,
+        msg: to_binary(&AstroportExecuteMsg::Swap {
+            offer_asset: Asset {
+                info: AssetInfo::NativeToken {
+                    denom: "uusd".to_string(),
+                },
+                amount: offer_asset.amount,
+            },
+            belief_price: None,
+            max_spread: None,
+            to: None,
+        })?,
+        funds: vec![coin(offer_asset.amount.u128(), "uusd")],
+    }))
+}
+
+/// ## Description
+/// Returns the message selling a token for UST on its Astroport pair.
+pub fn swap_for_ust(
+    querier: &QuerierWrapper,
+    offer_asset: Asset,
+    astroport_factory_address: Addr,
+) -> StdResult<CosmosMsg<TerraMsgWrapper>> {
+    let token = match &offer_asset.info {
+        AssetInfo::Token { contract_addr } => contract_addr.to_string(),
+        AssetInfo::NativeToken { denom } => {
+            return Err(StdError::generic_err(format!(
+                "Cannot sell {} on Astroport, only tokens are sold there",
+                denom
+            )))
+        }
+    };
+    let pair_contract = ust_pair(querier, astroport_factory_address, &offer_asset.info)?;
+
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: token,
+        msg: to_binary(&Cw20ExecuteMsg::Send {
+            contract: pair_contract.to_string(),
+            amount: offer_asset.amount,
+            msg: to_binary(&AstroportCw20HookMsg::Swap {
+                belief_price: None,
+                max_spread: None,
+                to: None,
+            })?,
+        })?,
+        funds: vec![],
+    }))
+}
+
+/// ## Description
+/// Fails when buying an asset with `ust_amount` on its Astroport pair returns an amount
+/// deviating from the oracle hub price by more than `max_price_deviation`.
+pub fn assert_price_deviation(
+    deps: Deps,
+    state: &State,
+    asset_info: &AssetInfo,
+    ust_amount: Uint128,
+    max_price_deviation: Decimal,
+) -> Result<(), ContractError> {
+    let pair_contract = ust_pair(
+        &deps.querier,
+        state.astroport_factory_address.clone(),
+        asset_info,
+    )?;
+    let return_amount = simulate(
+        &deps.querier,
+        pair_contract,
+        &Asset {
+            info: AssetInfo::NativeToken {
+                denom: "uusd".to_string(),
+            },
+            amount: ust_amount,
+        },
+    )?
+    .return_amount;
+
+    let price = OracleHub(state.oracle_hub_contract.clone())
+        .price(&deps.querier, asset_info.to_string(), None)?
+        .rate;
+    let scaled_price = price * DECIMAL_FRACTIONAL;
+    if scaled_price.is_zero() {
+        return Err(ContractError::ZeroOraclePrice {
+            asset: asset_info.to_string(),
+        });
+    }
+    let oracle_amount = ust_amount.multiply_ratio(DECIMAL_FRACTIONAL, scaled_price);
+
+    let deviation = if oracle_amount.is_zero() {
+        Decimal::one()
+    } else if return_amount > oracle_amount {
+        Decimal::from_ratio(return_amount - oracle_amount, oracle_amount)
+    } else {
+        Decimal::from_ratio(oracle_amount - return_amount, oracle_amount)
+    };
+    if deviation > max_price_deviation {
+        return Err(ContractError::PriceDeviation {
+            asset: asset_info.to_string(),
+            deviation,
+        });
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Mints the cluster token with the `ust_amt` sent by `user` and the `in_kind` target
+/// assets it received, the UST only buying what the in-kind assets fall short of target.
+/// With a `quote`, every asset provided to the cluster must reach its quoted amount less
+/// the tolerance.
+#[allow(clippy::too_many_arguments)]
+pub fn mint_ct(
+    deps: DepsMut,
+    env: Env,
+    cluster_address: String,
+    user: Addr,
+    mut ust_amt: Uint128,
+    allocation_mode: AllocationMode,
+    referrer: Option<String>,
+    referral_bps: Option<u16>,
+    in_kind: Vec<Asset>,
+    quote: Option<MintQuote>,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let cluster_state = load_cluster_state(deps.as_ref(), &cluster_address)?;
+    assert_in_kind(&cluster_address, &cluster_state, &in_kind)?;
+    // the quote covers the in-kind assets, already held by the contract when the mint
+    // measures what it received
+    let min_assets = quote
+        .map(|quote| quote_min_assets(&cluster_address, &cluster_state, &quote))
+        .transpose()?
+        .map(|min_assets| {
+            min_assets
+                .into_iter()
+                .map(|asset| Asset {
+                    amount: asset
+                        .amount
+                        .saturating_sub(amount_of(&in_kind, &asset.info)),
+                    info: asset.info,
+                })
+                .collect()
+        });
+    let ust_in = ust_amt;
+    let mut referral = None;
+    if let Some(referrer) = referrer {
+        let state = STATE.load(deps.storage)?;
+        let referrer = deps.api.addr_validate(referrer.as_ref())?;
+        let referral_bps = referral_bps.unwrap_or(0);
+        if referral_bps > state.max_referral_bps {
+            return Err(ContractError::ReferralFeeTooHigh {
+                max_bps: state.max_referral_bps,
+            });
+        }
+
+        let referral_fee = ust_amt.multiply_ratio(referral_bps, BPS_DENOMINATOR);
+        REFERRAL_STATS.update(
+            deps.storage,
+            &referrer,
+            |stats| -> StdResult<ReferralStats> {
+                let mut stats = stats.unwrap_or_default();
+                stats.volume = stats.volume.checked_add(ust_amt)?;
+                stats.fees = stats.fees.checked_add(referral_fee)?;
+                stats.mints += 1;
+                Ok(stats)
+            },
+        )?;
+
+        ust_amt -= referral_fee;
+        referral = Some((referrer, referral_fee));
+    } else if referral_bps.is_some() {
+        return Err(StdError::generic_err("referral_bps requires a referrer").into());
+    }
+
+    let receipt = MintReceipt {
+        ust_in,
+        referral_fee: referral
+            .as_ref()
+            .map(|(_, referral_fee)| *referral_fee)
+            .unwrap_or_default(),
+        ..MintReceipt::default()
+    };
+    let mut response = mint_with_ust(
+        deps,
+        env,
+        cluster_address,
+        cluster_state,
+        user,
+        ust_amt,
+        allocation_mode,
+        &in_kind,
+        None,
+        min_assets,
+        receipt,
+    )?;
+
+    if let Some((referrer, referral_fee)) = referral {
+        if !referral_fee.is_zero() {
+            // the referral share must leave before `_MintCT` collects the UST balance of the contract
+            response.messages.insert(
+                0,
+                SubMsg::new(BankMsg::Send {
+                    to_address: referrer.to_string(),
+                    amount: vec![coin(referral_fee.u128(), "uusd")],
+                }),
+            );
+        }
+        response = response.add_attributes(vec![
+            attr("referrer", referrer),
+            attr("referral_fee", referral_fee),
+        ]);
+    }
+
+    Ok(response)
+}
+
+pub fn mint_ct_exact(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cluster_address: String,
+    cluster_token_amount: Uint128,
+    allocation_mode: AllocationMode,
+    tolerance: Decimal,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    // only UST is searched and refunded, other coins would be left to the next mint
+    if let Some(other) = info.funds.iter().find(|c| c.denom != "uusd") {
+        return Err(StdError::generic_err(format!(
+            "MintCTExact only accepts uusd, {} sent",
+            other.denom
+        ))
+        .into());
+    }
+    let state = STATE.load(deps.storage)?;
+    let cluster_state = load_cluster_state(deps.as_ref(), &cluster_address)?;
+    let sent_amount = info
+        .funds
+        .iter()
+        .find(|c| c.denom == "uusd")
+        .map(|c| c.amount)
+        .unwrap_or_default();
+
+    let (ust_amt, _) = find_mint_ust_amount(
+        deps.as_ref(),
+        &SimulationContext::from_env(&env),
+        &state,
+        &cluster_state,
+        cluster_token_amount,
+        &allocation_mode,
+        tolerance,
+    )?;
+    if ust_amt > sent_amount {
+        return Err(ContractError::InsufficientFunds { required: ust_amt });
+    }
+    let refund = sent_amount - ust_amt;
+
+    let mut response = mint_with_ust(
+        deps,
+        env,
+        cluster_address,
+        cluster_state,
+        info.sender.clone(),
+        ust_amt,
+        allocation_mode,
+        &[],
+        Some(cluster_token_amount),
+        None,
+        MintReceipt {
+            ust_in: ust_amt,
+            refund,
+            ..MintReceipt::default()
+        },
+    )?;
+    if !refund.is_zero() {
+        // the refund must leave before `_MintCT` collects the UST balance of the contract
+        response.messages.insert(
+            0,
+            SubMsg::new(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: vec![coin(refund.u128(), "uusd")],
+            }),
+        );
+    }
+
+    Ok(response.add_attributes(vec![
+        attr("cluster_token_amount", cluster_token_amount),
+        attr("ust_amount", ust_amt),
+        attr("refund", refund),
+    ]))
+}
+
+/// ## Description
+/// Splits the UST balance of the contract between the clusters of `allocations` and mints
+/// each cluster token to `user`. Assets shared between the clusters are bought once and
+/// split by `_MintMany`.
+pub fn mint_many(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    allocations: Vec<(String, Uint128)>,
+    min_outputs: Option<Vec<Uint128>>,
+    allocation_mode: AllocationMode,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    // the whole UST balance is spent, other coins would be left to the next mint
+    if let Some(other) = info.funds.iter().find(|c| c.denom != "uusd") {
+        return Err(StdError::generic_err(format!(
+            "MintMany only accepts uusd, {} sent",
+            other.denom
+        ))
+        .into());
+    }
+    let user = info.sender;
+    if let Some(min_outputs) = &min_outputs {
+        if min_outputs.len() != allocations.len() {
+            return Err(StdError::generic_err("min_outputs must match the allocations").into());
+        }
+    }
+    let state = STATE.load(deps.storage)?;
+    let ust_amt = query_balance(
+        &deps.querier,
+        env.contract.address.clone(),
+        "uusd".to_string(),
+    )?;
+    // the deposit is a single mint, however many clusters it is split between
+    assert_mint_cap(&state, ust_amt)?;
+
+    let ctx = SimulationContext::from_env(&env);
+    let plans = plan_mint_many(
+        deps.as_ref(),
+        &ctx,
+        &state,
+        &allocations,
+        ust_amt,
+        &allocation_mode,
+    )?;
+
+    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
+    let mut events: Vec<Event> = vec![];
+    let mut mints: Vec<ClusterMint> = vec![];
+
+    let protocol_fee: Uint128 = plans.iter().map(|plan| plan.protocol_fee).sum();
+    if !protocol_fee.is_zero() {
+        msgs.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: state.fee_collector.to_string(),
+            amount: vec![coin(protocol_fee.u128(), "uusd")],
+        }));
+    }
+
+    for (index, plan) in plans.into_iter().enumerate() {
+        consume_window_capacity(
+            deps.storage,
+            &state,
+            &plan.cluster,
+            env.block.time.seconds(),
+            plan.ust_in,
+        )?;
+
+        events.push(Event::new("mint_ct").add_attributes(vec![
+            attr("action", "mint_ct"),
+            attr("user", user.clone()),
+            attr("cluster", plan.cluster.clone()),
+            attr("ust_in", plan.ust_in),
+            attr("allocation", allocation_mode.to_string()),
+        ]));
+        mints.push(ClusterMint {
+            cluster_address: plan.cluster.to_string(),
+            assets: plan.basket(),
+            cluster_token: plan.cluster_state.cluster_token,
+            min_tokens: min_outputs.as_ref().map(|min_outputs| min_outputs[index]),
+            receipt: MintReceipt {
+                ust_in: plan.ust_in,
+                protocol_fee: plan.protocol_fee,
+                ..MintReceipt::default()
+            },
+        });
+    }
+
+    let baskets: Vec<Vec<Asset>> = mints.iter().map(|mint| mint.assets.clone()).collect();
+    let mut swaps: Vec<SwapLeg> = vec![];
+    for asset in aggregate_assets(&baskets)? {
+        if asset.amount.is_zero() {
+            continue;
+        }
+
+        let (msg, venue) = buy_asset(deps.as_ref(), &ctx, &state, &asset.info, asset.amount)?;
+        swaps.push(swap_leg(&asset.info, venue, asset.amount));
+        msgs.extend(msg);
+    }
+
+    let balances_before = balances_before_swaps(
+        deps.as_ref(),
+        &env.contract.address,
+        swaps.iter().map(|leg| &leg.asset),
+    )?;
+    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::_MintMany {
+            user: user.to_string(),
+            swaps,
+            balances_before,
+            mints,
+        })?,
+        funds: vec![],
+    }));
+
+    Ok(Response::new().add_messages(msgs).add_events(events))
+}
+
+/// ## Description
+/// Handles the tokens sent to the contract along with a [`Cw20HookMsg`].
+pub fn receive_cw20(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    match from_binary(&cw20_msg.msg)? {
+        Cw20HookMsg::MintCT {
+            cluster_address,
+            allocation,
+            referrer,
+            referral_bps,
+            quote,
+        } => {
+            let user = deps.api.addr_validate(cw20_msg.sender.as_ref())?;
+            // a cw20 send carries no UST, whatever UST the contract holds is not the user's
+            mint_ct(
+                deps,
+                env,
+                cluster_address,
+                user,
+                Uint128::zero(),
+                allocation.unwrap_or(AllocationMode::Target),
+                referrer,
+                referral_bps,
+                vec![Asset {
+                    info: AssetInfo::Token {
+                        contract_addr: info.sender,
+                    },
+                    amount: cw20_msg.amount,
+                }],
+                quote,
+            )
+        }
+        Cw20HookMsg::SwitchCluster {
+            to_cluster,
+            min_out,
+        } => {
+            let user = deps.api.addr_validate(cw20_msg.sender.as_ref())?;
+            switch_cluster(
+                deps,
+                env,
+                info.sender,
+                user,
+                cw20_msg.amount,
+                to_cluster,
+                min_out,
+            )
+        }
+    }
+}
+
+/// ## Description
+/// Redeems `amount` of `cluster_token` pro rata from its cluster, then `_SwitchSell` and
+/// `_SwitchBuy` trade only the difference between the redeemed basket and the target of
+/// `to_cluster` before `_MintCT` mints its cluster token to `user`.
+pub fn switch_cluster(
+    deps: DepsMut,
+    env: Env,
+    cluster_token: Addr,
+    user: Addr,
+    amount: Uint128,
+    to_cluster: String,
+    min_out: Option<Uint128>,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    if amount.is_zero() {
+        return Err(StdError::generic_err("Cluster token amount must be positive").into());
+    }
+    let state = STATE.load(deps.storage)?;
+    let from_cluster = find_cluster_by_token(deps.as_ref(), &state, &cluster_token)?;
+    if deps.api.addr_validate(to_cluster.as_ref())? == from_cluster {
+        return Err(ContractError::SameCluster {
+            cluster: to_cluster,
+        });
+    }
+    load_cluster_state(deps.as_ref(), &to_cluster)?;
+
+    let event = Event::new("switch_cluster").add_attributes(vec![
+        attr("action", "switch_cluster"),
+        attr("user", user.clone()),
+        attr("from_cluster", from_cluster.clone()),
+        attr("to_cluster", to_cluster.clone()),
+        attr("cluster_tokens_in", amount),
+    ]);
+
+    let msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: cluster_token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                spender: state.incentive_contract.to_string(),
+                amount,
+                expires: None,
+            })?,
+            funds: vec![],
+        }),
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: state.incentive_contract.to_string(),
+            msg: to_binary(&IncentivesMsg::IncentivesRedeem {
+                cluster_contract: from_cluster.to_string(),
+                max_tokens: amount,
+                asset_amounts: None,
+            })?,
+            funds: vec![],
+        }),
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_binary(&ExecuteMsg::_SwitchSell {
+                from_cluster: from_cluster.to_string(),
+                to_cluster,
+                user: user.to_string(),
+                min_out,
+            })?,
+            funds: vec![],
+        }),
+    ];
+
+    Ok(Response::new().add_messages(msgs).add_event(event))
+}
+
+/// ## Description
+/// Swaps `ust_amt` into the target assets of a cluster and mints the cluster token to `user`
+/// along with the `in_kind` assets held by the contract, failing when the cluster mints
+/// fewer than `min_tokens` or the swaps add less than `min_assets` to any asset. `receipt`
+/// holds the amounts settled by the caller and is completed with the protocol fee and the
+/// swaps.
+#[allow(clippy::too_many_arguments)]
+pub fn mint_with_ust(
+    deps: DepsMut,
+    env: Env,
+    cluster_address: String,
+    cluster_state: ClusterStateResponse,
+    user: Addr,
+    ust_amt: Uint128,
+    allocation_mode: AllocationMode,
+    in_kind: &[Asset],
+    min_tokens: Option<Uint128>,
+    min_assets: Option<Vec<Asset>>,
+    mut receipt: MintReceipt,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    consume_mint_capacity(
+        deps.storage,
+        &state,
+        &deps.api.addr_validate(cluster_address.as_ref())?,
+        env.block.time.seconds(),
+        ust_amt,
+    )?;
+
+    let ctx = SimulationContext::from_env(&env);
+    let protocol_fee = compute_protocol_fee(&state, ust_amt);
+    let allocation = mint_allocation(
+        deps.as_ref(),
+        &ctx,
+        &state,
+        &cluster_state,
+        ust_amt - protocol_fee,
+        &allocation_mode,
+        in_kind,
+    )?;
+
+    let mut event = Event::new("mint_ct").add_attributes(vec![
+        attr("action", "mint_ct"),
+        attr("user", user.clone()),
+        attr("cluster", cluster_address.clone()),
+        attr("ust_in", receipt.ust_in),
+        attr("allocation", allocation_mode.to_string()),
+    ]);
+    // attributes may not be empty, so the in-kind assets are only listed when there are any
+    if !in_kind.is_empty() {
+        event = event.add_attribute(
+            "in_kind",
+            in_kind
+                .iter()
+                .map(|asset| asset.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+    }
+
+    let mut natives: Vec<String> = vec![];
+    let mut tokens: Vec<String> = vec![];
+    let mut msgs: Vec<CosmosMsg<TerraMsgWrapper>> = vec![];
+
+    receipt.protocol_fee = protocol_fee;
+    if !protocol_fee.is_zero() {
+        msgs.push(CosmosMsg::Bank(BankMsg::Send {
+            to_address: state.fee_collector.to_string(),
+            amount: vec![coin(protocol_fee.u128(), "uusd")],
+        }));
+    }
+
+    for (asset, asset_ratio) in cluster_state.target.iter().zip(allocation) {
+        match &asset.info {
+            AssetInfo::NativeToken { denom } => natives.push(denom.clone()),
+            AssetInfo::Token { contract_addr } => tokens.push(contract_addr.to_string()),
+        }
+        if asset_ratio.is_zero() {
+            continue;
+        }
+
+        let (msg, venue) = buy_asset(deps.as_ref(), &ctx, &state, &asset.info, asset_ratio)?;
+        receipt
+            .swaps
+            .push(swap_leg(&asset.info, venue, asset_ratio));
+        msgs.extend(msg);
+    }
+
+    let balances_before = balances_before_swaps(
+        deps.as_ref(),
+        &env.contract.address,
+        cluster_state.target.iter().map(|asset| &asset.info),
+    )?;
+    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: env.contract.address.to_string(),
+        msg: to_binary(&ExecuteMsg::_MintCT {
+            cluster_address,
+            tokens,
+            natives,
+            cluster_token: cluster_state.cluster_token,
+            user: user.to_string(),
+            min_tokens,
+            min_assets,
+            balances_before,
+            receipt,
+        })?,
+        funds: vec![],
+    }));
+
+    Ok(Response::new().add_messages(msgs).add_event(event))
+}
+
+/// ## Description
+/// Returns the message buying an asset with `ust_amount` and the name of its venue,
+/// without any message for UST itself. Native assets are bought on their Astroport pair
+/// when `simulate_asset` quotes it above the Terra market. Any Astroport purchase is
+/// checked against the oracle hub when `max_price_deviation` is set.
+fn buy_asset(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    asset_info: &AssetInfo,
+    ust_amount: Uint128,
+) -> Result<(Option<CosmosMsg<TerraMsgWrapper>>, &'static str), ContractError> {
+    match asset_info {
+        AssetInfo::NativeToken { denom } if denom == "uusd" => Ok((None, "none")),
+        AssetInfo::NativeToken { denom } => {
+            let simulation = simulate_asset(deps, ctx, state, asset_info, ust_amount)?;
+            if let SwapRoute::Astroport { .. } = simulation.route {
+                if let Some(max_price_deviation) = state.max_price_deviation {
+                    assert_price_deviation(
+                        deps,
+                        state,
+                        asset_info,
+                        ust_amount,
+                        max_price_deviation,
+                    )?;
+                }
+
+                return Ok((
+                    Some(swap_to_ust(
+                        &deps.querier,
+                        Asset {
+                            info: asset_info.clone(),
+                            amount: ust_amount,
+                        },
+                        state.astroport_factory_address.clone(),
+                    )?),
+                    "astroport",
+                ));
+            }
+
+            Ok((
+                Some(create_swap_msg(
+                    coin(ust_amount.u128(), "uusd"),
+                    denom.clone(),
+                )),
+                "terra_market",
+            ))
+        }
+        AssetInfo::Token { contract_addr } if *contract_addr == state.aust_token_address => Ok((
+            Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: state.anchor_market_contract.to_string(),
+                msg: to_binary(&AnchorMsg::DepositStable {})?,
+                funds: vec![coin(ust_amount.u128(), "uusd")],
+            })),
+            "anchor",
+        )),
+        AssetInfo::Token { .. } => {
+            if let Some(max_price_deviation) = state.max_price_deviation {
+                assert_price_deviation(deps, state, asset_info, ust_amount, max_price_deviation)?;
+            }
+
+            Ok((
+                Some(swap_to_ust(
+                    &deps.querier,
+                    Asset {
+                        info: asset_info.clone(),
+                        amount: ust_amount,
+                    },
+                    state.astroport_factory_address.clone(),
+                )?),
+                "astroport",
+            ))
+        }
+    }
+}
+
+/// ## Description
+/// Returns the message selling an asset for UST and the name of its venue, without any
+/// message for UST itself. Native assets are always sold on the Terra market.
+fn sell_asset(
+    deps: Deps,
+    state: &State,
+    asset: &Asset,
+) -> Result<(Option<CosmosMsg<TerraMsgWrapper>>, &'static str), ContractError> {
+    match &asset.info {
+        AssetInfo::NativeToken { denom } if denom == "uusd" => Ok((None, "none")),
+        AssetInfo::NativeToken { denom } => Ok((
+            Some(create_swap_msg(
+                coin(asset.amount.u128(), denom.clone()),
+                "uusd".to_string(),
+            )),
+            "terra_market",
+        )),
+        AssetInfo::Token { contract_addr } if *contract_addr == state.aust_token_address => Ok((
+            Some(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: contract_addr.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: state.anchor_market_contract.to_string(),
+                    amount: asset.amount,
+                    msg: to_binary(&AnchorCw20HookMsg::RedeemStable {})?,
+                })?,
+                funds: vec![],
+            })),
+            "anchor",
+        )),
+        AssetInfo::Token { .. } => Ok((
+            Some(swap_for_ust(
+                &deps.querier,
+                asset.clone(),
+                state.astroport_factory_address.clone(),
+            )?),
+            "astroport",
+        )),
+    }
+}
+
+fn swap_leg(asset: &AssetInfo, venue: &str, ust_in: Uint128) -> SwapLeg {
+    SwapLeg {
+        asset: asset.clone(),
+        venue: venue.to_string(),
+        ust_in,
+    }
+}
+
+/// ## Description
+/// The share of a `MintMany` going to one cluster.
+pub struct ClusterMintPlan {
+    pub cluster: Addr,
+    pub cluster_state: ClusterStateResponse,
+    /// UST of the deposit allocated to the cluster, fees included
+    pub ust_in: Uint128,
+    pub protocol_fee: Uint128,
+    /// UST spent on each target asset, aligned with `cluster_state.target`
+    pub allocation: Vec<Uint128>,
+}
+
+impl ClusterMintPlan {
+    /// ## Description
+    /// Returns the UST spent on each target asset.
+    pub fn basket(&self) -> Vec<Asset> {
+        self.cluster_state
+            .target
+            .iter()
+            .zip(self.allocation.iter())
+            .map(|(asset, amount)| Asset {
+                info: asset.info.clone(),
+                amount: *amount,
+            })
+            .collect()
+    }
+}
+
+/// ## Description
+/// Splits `ust_amt` between the clusters of `allocations` by weight, then between the
+/// target assets of each cluster following `mode`.
+pub fn plan_mint_many(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    allocations: &[(String, Uint128)],
+    ust_amt: Uint128,
+    mode: &AllocationMode,
+) -> Result<Vec<ClusterMintPlan>, ContractError> {
+    if allocations.is_empty() {
+        return Err(StdError::generic_err("At least one cluster is required").into());
+    }
+    for (index, (cluster_address, weight)) in allocations.iter().enumerate() {
+        if weight.is_zero() {
+            return Err(StdError::generic_err(format!(
+                "Weight of cluster {} must be positive",
+                cluster_address
+            ))
+            .into());
+        }
+        if allocations[..index]
+            .iter()
+            .any(|(other, _)| other == cluster_address)
+        {
+            return Err(ContractError::DuplicateCluster {
+                cluster: cluster_address.clone(),
+            });
+        }
+    }
+
+    let weights: Vec<Uint128> = allocations.iter().map(|(_, weight)| *weight).collect();
+    allocations
+        .iter()
+        .zip(split_by_weights(ust_amt, &weights)?)
+        .map(|((cluster_address, _), ust_in)| {
+            let cluster_state = load_cluster_state(deps, cluster_address)?;
+            let protocol_fee = compute_protocol_fee(state, ust_in);
+            let allocation = compute_allocation(
+                deps,
+                ctx,
+                state,
+                &cluster_state,
+                ust_in - protocol_fee,
+                mode,
+            )?;
+
+            Ok(ClusterMintPlan {
+                cluster: deps.api.addr_validate(cluster_address)?,
+                cluster_state,
+                ust_in,
+                protocol_fee,
+                allocation,
+            })
+        })
+        .collect()
+}
+
+/// ## Description
+/// Sums the amounts of each asset over `baskets`, in the order the assets first appear.
+pub fn aggregate_assets(baskets: &[Vec<Asset>]) -> StdResult<Vec<Asset>> {
+    let mut totals: Vec<Asset> = vec![];
+    for asset in baskets.iter().flatten() {
+        match totals.iter_mut().find(|total| total.info == asset.info) {
+            Some(total) => total.amount = total.amount.checked_add(asset.amount)?,
+            None => totals.push(asset.clone()),
+        }
+    }
+
+    Ok(totals)
+}
+
+/// ## Description
+/// Splits each asset of `bought` between `baskets` pro rata of the amount of the asset in
+/// each basket. Returns for each basket its distinct assets with their share.
+pub fn split_assets(baskets: &[Vec<Asset>], bought: &[Asset]) -> StdResult<Vec<Vec<Asset>>> {
+    let mut shares: Vec<Vec<Asset>> = vec![vec![]; baskets.len()];
+    for asset in bought {
+        let weights = baskets
+            .iter()
+            .map(|basket| {
+                basket
+                    .iter()
+                    .filter(|item| item.info == asset.info)
+                    .try_fold(Uint128::zero(), |total, item| {
+                        total.checked_add(item.amount)
+                    })
+            })
+            .collect::<Result<Vec<Uint128>, _>>()?;
+        let amounts = if weights.iter().all(|weight| weight.is_zero()) {
+            vec![Uint128::zero(); weights.len()]
+        } else {
+            split_by_weights(asset.amount, &weights)?
+        };
+
+        for (share, amount) in shares.iter_mut().zip(amounts) {
+            share.push(Asset {
+                info: asset.info.clone(),
+                amount,
+            });
+        }
+    }
+
+    // keep the assets of each basket, in its order
+    Ok(baskets
+        .iter()
+        .zip(shares)
+        .map(|(basket, share)| {
+            let mut assets: Vec<Asset> = vec![];
+            for item in basket {
+                if assets.iter().any(|asset| asset.info == item.info) {
+                    continue;
+                }
+                assets.push(Asset {
+                    info: item.info.clone(),
+                    amount: share
+                        .iter()
+                        .find(|asset| asset.info == item.info)
+                        .map(|asset| asset.amount)
+                        .unwrap_or_default(),
+                });
+            }
+            assets
+        })
+        .collect())
+}
+
+/// ## Description
+/// The trades turning a basket redeemed from one cluster into the target basket of another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwitchPlan {
+    /// Amounts of the redeemed assets held above the new target, sold for UST
+    pub sells: Vec<Asset>,
+    /// UST value missing of each target asset of the new cluster, aligned with its target
+    pub deficits: Vec<Asset>,
+    /// UST value of the redeemed assets at the prices of the old cluster
+    pub value: Uint128,
+}
+
+/// ## Description
+/// Values `redeemed` at the prices of `from_state` and splits that value by the target
+/// weights of `to_state`. Each asset held above its new target is sold down to it and
+/// each asset held below target records the UST value it misses. Assets are priced by
+/// the cluster listing them.
+pub fn plan_switch(
+    redeemed: &[Asset],
+    from_state: &ClusterStateResponse,
+    to_state: &ClusterStateResponse,
+) -> StdResult<SwitchPlan> {
+    let total_value =
+        redeemed
+            .iter()
+            .try_fold(Uint128::zero(), |total, asset| -> StdResult<Uint128> {
+                let value = asset.amount * cluster_price(from_state, &asset.info)?;
+                Ok(total.checked_add(value)?)
+            })?;
+
+    let weights: Vec<Uint128> = to_state.target.iter().map(|asset| asset.amount).collect();
+    let desired = to_state
+        .target
+        .iter()
+        .zip(split_by_weights(total_value, &weights)?)
+        .map(|(asset, value)| {
+            let price = cluster_price(to_state, &asset.info)?;
+            if price.is_zero() {
+                return Err(StdError::generic_err(format!(
+                    "Price of {} must be positive",
+                    asset.info
+                )));
+            }
+
+            Ok(Asset {
+                info: asset.info.clone(),
+                amount: value.multiply_ratio(DECIMAL_FRACTIONAL, price * DECIMAL_FRACTIONAL),
+            })
+        })
+        .collect::<StdResult<Vec<Asset>>>()?;
+
+    let sells = redeemed
+        .iter()
+        .map(|asset| Asset {
+            info: asset.info.clone(),
+            amount: asset
+                .amount
+                .saturating_sub(amount_of(&desired, &asset.info)),
+        })
+        .filter(|asset| !asset.amount.is_zero())
+        .collect();
+    let deficits = desired
+        .iter()
+        .map(|asset| {
+            let missing = asset
+                .amount
+                .saturating_sub(amount_of(redeemed, &asset.info));
+
+            Ok(Asset {
+                info: asset.info.clone(),
+                amount: missing * cluster_price(to_state, &asset.info)?,
+            })
+        })
+        .collect::<StdResult<Vec<Asset>>>()?;
+
+    Ok(SwitchPlan {
+        sells,
+        deficits,
+        value: total_value,
+    })
+}
+
+/// ## Description
+/// Returns the price of a target asset of a cluster.
+fn cluster_price(cluster_state: &ClusterStateResponse, info: &AssetInfo) -> StdResult<Decimal> {
+    let price = cluster_state
+        .target
+        .iter()
+        .position(|asset| asset.info == *info)
+        .and_then(|index| cluster_state.prices.get(index))
+        .ok_or_else(|| {
+            StdError::generic_err(format!(
+                "No price for {} in cluster {}",
+                info, cluster_state.cluster_contract_address
+            ))
+        })?;
+
+    Decimal::from_str(price)
+}
+
+/// ## Description
+/// Returns the amount of an asset in `assets`, zero when missing.
+fn amount_of(assets: &[Asset], info: &AssetInfo) -> Uint128 {
+    assets
+        .iter()
+        .find(|asset| asset.info == *info)
+        .map(|asset| asset.amount)
+        .unwrap_or_default()
+}
+
+/// ## Description
+/// Returns the allowlisted cluster minting `cluster_token`, checked against the cluster
+/// factory when one is configured.
+pub fn find_cluster_by_token(
+    deps: Deps,
+    state: &State,
+    cluster_token: &Addr,
+) -> Result<Addr, ContractError> {
+    let cluster = CLUSTER_TOKENS
+        .may_load(deps.storage, cluster_token)?
+        .filter(|cluster| ALLOWED_CLUSTERS.has(deps.storage, cluster))
+        .ok_or_else(|| ContractError::UnknownClusterToken {
+            cluster_token: cluster_token.to_string(),
+        })?;
+
+    if let Some(cluster_factory) = &state.cluster_factory {
+        verify_cluster_token(
+            deps,
+            cluster_factory,
+            cluster.as_str(),
+            cluster_token.as_str(),
+        )?;
+    }
+
+    Ok(cluster)
+}
+
+/// ## Description
+/// Returns the state of a cluster with its target deduplicated, failing when the cluster
+/// is not allowlisted.
+pub fn load_cluster_state(
+    deps: Deps,
+    cluster_address: &str,
+) -> Result<ClusterStateResponse, ContractError> {
+    let cluster = deps.api.addr_validate(cluster_address)?;
+    if !ALLOWED_CLUSTERS.has(deps.storage, &cluster) {
+        return Err(ContractError::ClusterNotAllowed {
+            cluster: cluster.to_string(),
+        });
+    }
+
+    Ok(normalize_cluster_state(
+        ClusterContract(cluster).state(&deps.querier)?,
+    )?)
+}
+
+/// ## Description
+/// Merges the target entries listing the same asset, summing their weights and inventory
+/// and keeping the first price, so that every asset is bought and provided once.
+pub fn normalize_cluster_state(
+    mut cluster_state: ClusterStateResponse,
+) -> StdResult<ClusterStateResponse> {
+    let mut target: Vec<Asset> = vec![];
+    let mut inv: Vec<Uint128> = vec![];
+    let mut prices: Vec<String> = vec![];
+    for (index, asset) in cluster_state.target.iter().enumerate() {
+        let amount = cluster_state.inv.get(index).copied().unwrap_or_default();
+        match target.iter().position(|item| item.info == asset.info) {
+            Some(position) => {
+                target[position].amount = target[position].amount.checked_add(asset.amount)?;
+                inv[position] = inv[position].checked_add(amount)?;
+            }
+            None => {
+                target.push(asset.clone());
+                inv.push(amount);
+                prices.push(cluster_state.prices.get(index).cloned().unwrap_or_default());
+            }
+        }
+    }
+
+    cluster_state.target = target;
+    cluster_state.inv = inv;
+    cluster_state.prices = prices;
+
+    Ok(cluster_state)
+}
+
+/// ## Description
+/// Checks that a cluster was created by the cluster factory and that `cluster_token`
+/// is the token registered in its config.
+pub fn verify_cluster_token(
+    deps: Deps,
+    cluster_factory: &Addr,
+    cluster_address: &str,
+    cluster_token: &str,
+) -> Result<(), ContractError> {
+    let cluster = deps.api.addr_validate(cluster_address)?;
+    if !ClusterFactory(cluster_factory.clone()).cluster_exists(&deps.querier, &cluster)? {
+        return Err(ContractError::ClusterNotRegistered {
+            cluster: cluster.to_string(),
+        });
+    }
+
+    let config = ClusterContract(cluster.clone()).config(&deps.querier)?;
+    if config.factory != cluster_factory.as_str()
+        || config.cluster_token.as_deref() != Some(cluster_token)
+    {
+        return Err(ContractError::ClusterTokenMismatch {
+            cluster: cluster.to_string(),
+            cluster_token: cluster_token.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Returns the oracle price of an asset, failing when it is older than `ctx.max_price_age`.
+pub fn get_fresh_price(
+    deps: Deps,
+    ctx: &SimulationContext,
+    contract: &Addr,
+    asset_token: String,
+) -> StdResult<PriceResponse> {
+    let price =
+        OracleHub(contract.clone()).price(&deps.querier, asset_token.clone(), ctx.max_price_age)?;
+    if let Some(max_price_age) = ctx.max_price_age {
+        if ctx.block_time.saturating_sub(price.last_updated) > max_price_age {
+            return Err(StdError::generic_err(format!(
+                "Price of {} is older than {} seconds",
+                asset_token, max_price_age
+            )));
+        }
+    }
+
+    Ok(price)
+}
+
+/// ## Description
+/// Fails when an asset provided in kind is not a target asset of the cluster.
+pub fn assert_in_kind(
+    cluster_address: &str,
+    cluster_state: &ClusterStateResponse,
+    in_kind: &[Asset],
+) -> Result<(), ContractError> {
+    for asset in in_kind {
+        if !cluster_state
+            .target
+            .iter()
+            .any(|target| target.info == asset.info)
+        {
+            return Err(ContractError::AssetNotInCluster {
+                asset: asset.info.to_string(),
+                cluster: cluster_address.to_string(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Returns the minimum amount of each target asset a mint honouring `quote` provides.
+pub fn quote_min_assets(
+    cluster_address: &str,
+    cluster_state: &ClusterStateResponse,
+    quote: &MintQuote,
+) -> Result<Vec<Asset>, ContractError> {
+    if quote.create_asset_amounts.len() != cluster_state.target.len() {
+        return Err(ContractError::QuoteMismatch {
+            cluster: cluster_address.to_string(),
+            assets: cluster_state.target.len(),
+        });
+    }
+
+    Ok(cluster_state
+        .target
+        .iter()
+        .zip(quote.create_asset_amounts.iter())
+        .map(|(asset, amount)| Asset {
+            info: asset.info.clone(),
+            amount: amount.saturating_sub(*amount * quote.tolerance),
+        })
+        .collect())
+}
+
+/// ## Description
+/// Checks that the `received` assets reach `min_assets`.
+pub fn assert_min_assets(received: &[Asset], min_assets: &[Asset]) -> Result<(), ContractError> {
+    for min in min_assets {
+        let received = amount_of(received, &min.info);
+        if received < min.amount {
+            return Err(ContractError::QuoteNotMet {
+                asset: min.info.to_string(),
+                received,
+                min: min.amount,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// ## Description
+/// Splits `ust_amt` between the target assets of a cluster following `mode`, or filling the
+/// shortfall of the `in_kind` assets when there are any.
+pub fn mint_allocation(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    cluster_state: &ClusterStateResponse,
+    ust_amt: Uint128,
+    mode: &AllocationMode,
+    in_kind: &[Asset],
+) -> StdResult<Vec<Uint128>> {
+    if in_kind.is_empty() {
+        return compute_allocation(deps, ctx, state, cluster_state, ust_amt, mode);
+    }
+    if *mode != AllocationMode::Target {
+        return Err(StdError::generic_err(
+            "In-kind assets only support the target allocation",
+        ));
+    }
+
+    in_kind_allocation(ust_amt, cluster_state, in_kind)
+}
+
+/// ## Description
+/// Splits `ust_amt` so that, with the `in_kind` assets, the basket provided to the cluster
+/// lands on its target weights. Each asset receives UST in proportion to how far its
+/// in-kind value falls short of its target share of the combined value.
+pub fn in_kind_allocation(
+    ust_amt: Uint128,
+    cluster_state: &ClusterStateResponse,
+    in_kind: &[Asset],
+) -> StdResult<Vec<Uint128>> {
+    let target = &cluster_state.target;
+    if cluster_state.prices.len() != target.len() {
+        return Err(StdError::generic_err(
+            "cluster prices and target lengths mismatch",
+        ));
+    }
+    if ust_amt.is_zero() {
+        return Ok(vec![Uint128::zero(); target.len()]);
+    }
 
-                msgs.push(create_swap_msg(coin(asset_ratio.into(), "uusd"), denom))
+    let weights: Vec<Uint128> = target.iter().map(|asset| asset.amount).collect();
+    let total_target_weight = total_weight(&weights)?;
+    let values = target
+        .iter()
+        .zip(cluster_state.prices.iter())
+        .map(|(asset, price)| Ok(amount_of(in_kind, &asset.info) * Decimal::from_str(price)?))
+        .collect::<StdResult<Vec<Uint128>>>()?;
+    let total_value = values
+        .iter()
+        .try_fold(ust_amt, |total, value| total.checked_add(*value))?;
+
+    let shortfalls: Vec<Uint128> = weights
+        .iter()
+        .zip(values.iter())
+        .map(|(weight, value)| {
+            total_value
+                .multiply_ratio(*weight, total_target_weight)
+                .saturating_sub(*value)
+        })
+        .collect();
+
+    if shortfalls.iter().all(|shortfall| shortfall.is_zero()) {
+        split_by_weights(ust_amt, &weights)
+    } else {
+        split_by_weights(ust_amt, &shortfalls)
+    }
+}
+
+/// ## Description
+/// Splits `ust_amt` between the target assets of a cluster following `mode`.
+/// The returned UST amounts are aligned with `cluster_state.target`.
+pub fn compute_allocation(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    cluster_state: &ClusterStateResponse,
+    ust_amt: Uint128,
+    mode: &AllocationMode,
+) -> StdResult<Vec<Uint128>> {
+    match mode {
+        AllocationMode::Target => target_allocation(ust_amt, &cluster_state.target),
+        AllocationMode::Rebalance => rebalance_allocation(ust_amt, cluster_state),
+        AllocationMode::Optimize => {
+            let target = target_allocation(ust_amt, &cluster_state.target)?;
+            let rebalance = rebalance_allocation(ust_amt, cluster_state)?;
+
+            let mut best_allocation = target.clone();
+            let mut best_create_tokens = Uint128::zero();
+            for step in 0..=OPTIMIZE_STEPS {
+                let allocation = blend_allocation(&target, &rebalance, step, OPTIMIZE_STEPS);
+                let create_asset_amounts =
+                    simulate_assets(deps, ctx, state, &cluster_state.target, &allocation)?
+                        .iter()
+                        .map(|asset| asset.return_amount)
+                        .collect();
+                let create_tokens = query_create_tokens(
+                    deps,
+                    ctx.block_height,
+                    cluster_state,
+                    create_asset_amounts,
+                )?
+                .create_tokens;
+
+                if step == 0 || create_tokens > best_create_tokens {
+                    best_create_tokens = create_tokens;
+                    best_allocation = allocation;
+                }
             }
-            AssetInfo::Token { contract_addr } => {
-                tokens.push(contract_addr.to_string());
-                attrs.push(attr("swap_ust_to_token_", contract_addr.clone()));
-                attrs.push(attr("amount", asset_ratio.clone()));
 
-                if contract_addr != state.aust_token_address {
-                    msgs.push(swap_to_ust(
+            Ok(best_allocation)
+        }
+    }
+}
+
+/// ## Description
+/// Splits `ust_amt` between the assets in proportion to their target weights.
+pub fn target_allocation(ust_amt: Uint128, target: &[Asset]) -> StdResult<Vec<Uint128>> {
+    let weights: Vec<Uint128> = target.iter().map(|asset| asset.amount).collect();
+
+    split_by_weights(ust_amt, &weights)
+}
+
+/// ## Description
+/// Splits `amount` in proportion to `weights`. Each share is rounded down and the rounding
+/// dust goes to the heaviest weight, so the shares always sum up to `amount`.
+/// Fails when the weights sum up to zero or overflow.
+pub fn split_by_weights(amount: Uint128, weights: &[Uint128]) -> StdResult<Vec<Uint128>> {
+    let total_weight = total_weight(weights)?;
+    let mut shares: Vec<Uint128> = weights
+        .iter()
+        .map(|weight| amount.multiply_ratio(*weight, total_weight))
+        .collect();
+    let dust = amount - shares.iter().sum::<Uint128>();
+    if let Some((heaviest, _)) = weights
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, weight)| **weight)
+    {
+        shares[heaviest] += dust;
+    }
+
+    Ok(shares)
+}
+
+/// ## Description
+/// Returns the sum of `weights`, failing when it is zero or overflows.
+fn total_weight(weights: &[Uint128]) -> StdResult<Uint128> {
+    let total_weight = weights
+        .iter()
+        .try_fold(Uint128::zero(), |total, weight| total.checked_add(*weight))?;
+    if total_weight.is_zero() {
+        return Err(StdError::generic_err("Total weight must be positive"));
+    }
+
+    Ok(total_weight)
+}
+
+/// ## Description
+/// Splits `ust_amt` so that the cluster inventory moves toward its target weights.
+/// Each asset receives UST in proportion to how far its value after the mint would be
+/// below target; any UST left once every asset reached target is split by target weight.
+pub fn rebalance_allocation(
+    ust_amt: Uint128,
+    cluster_state: &ClusterStateResponse,
+) -> StdResult<Vec<Uint128>> {
+    let target = &cluster_state.target;
+    if cluster_state.inv.len() != target.len() || cluster_state.prices.len() != target.len() {
+        return Err(StdError::generic_err(
+            "cluster inventory, prices and target lengths mismatch",
+        ));
+    }
+
+    let weights: Vec<Uint128> = target.iter().map(|asset| asset.amount).collect();
+    let total_target_weight = total_weight(&weights)?;
+    let values = cluster_state
+        .inv
+        .iter()
+        .zip(cluster_state.prices.iter())
+        .map(|(inv, price)| Ok(*inv * Decimal::from_str(price)?))
+        .collect::<StdResult<Vec<Uint128>>>()?;
+    let total_value = values
+        .iter()
+        .try_fold(ust_amt, |total, value| total.checked_add(*value))?;
+
+    let deficits: Vec<Uint128> = weights
+        .iter()
+        .zip(values.iter())
+        .map(|(weight, value)| {
+            total_value
+                .multiply_ratio(*weight, total_target_weight)
+                .saturating_sub(*value)
+        })
+        .collect();
+    let total_deficit: Uint128 = deficits.iter().sum();
+
+    if total_deficit.is_zero() {
+        split_by_weights(ust_amt, &weights)
+    } else if total_deficit >= ust_amt {
+        split_by_weights(ust_amt, &deficits)
+    } else {
+        Ok(deficits
+            .iter()
+            .zip(split_by_weights(ust_amt - total_deficit, &weights)?)
+            .map(|(deficit, surplus)| *deficit + surplus)
+            .collect())
+    }
+}
+
+/// ## Description
+/// Returns the allocation `step / steps` of the way from `from` to `to`.
+fn blend_allocation(from: &[Uint128], to: &[Uint128], step: u128, steps: u128) -> Vec<Uint128> {
+    from.iter()
+        .zip(to.iter())
+        .map(|(a, b)| a.multiply_ratio(steps - step, steps) + b.multiply_ratio(step, steps))
+        .collect()
+}
+
+/// ## Description
+/// Simulates buying each target asset with the given UST allocation.
+pub fn simulate_assets(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    target: &[Asset],
+    allocation: &[Uint128],
+) -> StdResult<Vec<SimulateMintAsset>> {
+    target
+        .iter()
+        .zip(allocation.iter())
+        .map(|(asset, ust_amount)| simulate_asset(deps, ctx, state, &asset.info, *ust_amount))
+        .collect()
+}
+
+/// ## Description
+/// Simulates buying an asset with `ust_amount` through the venue `mint_ct` uses for it.
+pub fn simulate_asset(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    asset_info: &AssetInfo,
+    ust_amount: Uint128,
+) -> StdResult<SimulateMintAsset> {
+    let mut simulation = SimulateMintAsset {
+        info: asset_info.clone(),
+        ust_amount,
+        return_amount: Uint128::zero(),
+        route: SwapRoute::None {},
+        spread_amount: Uint128::zero(),
+        commission_amount: Uint128::zero(),
+    };
+
+    match asset_info {
+        AssetInfo::NativeToken { denom } => {
+            if denom == "uusd" {
+                simulation.return_amount = ust_amount;
+            } else {
+                simulation.route = SwapRoute::TerraMarket {};
+                if !ust_amount.is_zero() {
+                    let terra_querier = TerraQuerier::new(&deps.querier);
+                    simulation.return_amount = terra_querier
+                        .query_swap(coin(ust_amount.u128(), "uusd"), denom)?
+                        .receive
+                        .amount;
+
+                    // the market takes its spread, the Tobin tax between stablecoins, out of
+                    // the amount the oracle exchange rate gives
+                    let exchange_rate = terra_querier
+                        .query_exchange_rates("uusd".to_string(), vec![denom.to_string()])?
+                        .exchange_rates
+                        .first()
+                        .map(|item| item.exchange_rate)
+                        .ok_or_else(|| {
+                            StdError::generic_err(format!("No exchange rate for {}", denom))
+                        })?;
+                    simulation.spread_amount =
+                        (ust_amount * exchange_rate).saturating_sub(simulation.return_amount);
+
+                    // an Astroport pool of the asset against UST may beat the market
+                    if let Some(pair_contract) = ctx.try_pair_address(
                         &deps.querier,
-                        Asset {
-                            info: asset.info.clone(),
-                            amount: asset_ratio,
-                        },
-                        state.astroport_factory_address.clone(),
-                    )?);
-                } else {
-                    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                        contract_addr: state.anchor_market_contract.to_string(),
-                        msg: to_binary(&AnchorMsg::DepositStable {})?,
-                        funds: vec![coin(asset_ratio.u128(), "uusd")],
-                    }));
+                        &state.astroport_factory_address,
+                        asset_info,
+                    )? {
+                        let response =
+                            simulate_buy(&deps.querier, pair_contract.clone(), ust_amount)?;
+                        // once purchases are checked against the oracle hub, a pool it has no
+                        // price for is passed over for the market, priced by the Terra oracle
+                        let checkable = state.max_price_deviation.is_none()
+                            || OracleHub(state.oracle_hub_contract.clone())
+                                .price(&deps.querier, asset_info.to_string(), None)
+                                .is_ok();
+                        if checkable && response.return_amount > simulation.return_amount {
+                            simulation.route = SwapRoute::Astroport {
+                                pair_address: pair_contract.to_string(),
+                            };
+                            simulation.return_amount = response.return_amount;
+                            simulation.spread_amount = response.spread_amount;
+                            simulation.commission_amount = response.commission_amount;
+                        }
+                    }
+                }
+            }
+        }
+        AssetInfo::Token { contract_addr } => {
+            if *contract_addr != state.aust_token_address {
+                let pair_contract =
+                    ctx.pair_address(&deps.querier, &state.astroport_factory_address, asset_info)?;
+
+                simulation.route = SwapRoute::Astroport {
+                    pair_address: pair_contract.to_string(),
+                };
+                if !ust_amount.is_zero() {
+                    let response = simulate_buy(&deps.querier, pair_contract, ust_amount)?;
+
+                    simulation.return_amount = response.return_amount;
+                    simulation.spread_amount = response.spread_amount;
+                    simulation.commission_amount = response.commission_amount;
+                }
+            } else {
+                simulation.route = SwapRoute::Anchor {};
+                if !ust_amount.is_zero() {
+                    let price = get_fresh_price(
+                        deps,
+                        ctx,
+                        &state.oracle_hub_contract,
+                        contract_addr.to_string(),
+                    )?
+                    .rate;
+
+                    simulation.return_amount =
+                        ust_amount.multiply_ratio(DECIMAL_FRACTIONAL, price * DECIMAL_FRACTIONAL);
                 }
             }
         }
     }
 
-    msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-        contract_addr: env.contract.address.to_string(),
-        msg: to_binary(&ExecuteMsg::_MintCT {
-            cluster_address: cluster_address,
-            tokens,
-            natives,
-            cluster_token: cluster_state.cluster_token,
-            user: user.to_string(),
-        })?,
-        funds: vec![],
-    }));
+    Ok(simulation)
+}
 
-    Ok(Response::new().add_messages(msgs).add_attributes(attrs))
+/// ## Description
+/// Simulates offering `ust_amount` to an Astroport pair.
+fn simulate_buy(
+    querier: &QuerierWrapper,
+    pair_contract: Addr,
+    ust_amount: Uint128,
+) -> StdResult<SimulationResponse> {
+    simulate(
+        querier,
+        pair_contract,
+        &Asset {
+            info: AssetInfo::NativeToken {
+                denom: "uusd".to_string(),
+            },
+            amount: ust_amount,
+        },
+    )
 }
 
 /// ## Description
-/// Returns the state of a cluster.
-///
-/// ## Params
-/// - **deps** is an object of type [`Deps`].
-///
-/// - **cluster** is a reference to an object of type [`Addr`] which is
-///     the address of a cluster.
-pub fn get_cluster_state(deps: Deps, cluster: &Addr) -> StdResult<ClusterStateResponse> {
-    // Query the cluster state
-    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-        contract_addr: cluster.to_string(),
-        msg: to_binary(&QueryMsgNebula::ClusterState {})?,
-    }))
+/// Simulates selling an asset for UST through the venue `sell_asset` uses for it.
+pub fn simulate_sale(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    asset: &Asset,
+) -> StdResult<Uint128> {
+    if asset.amount.is_zero() {
+        return Ok(Uint128::zero());
+    }
+
+    match &asset.info {
+        AssetInfo::NativeToken { denom } if denom == "uusd" => Ok(asset.amount),
+        AssetInfo::NativeToken { denom } => Ok(TerraQuerier::new(&deps.querier)
+            .query_swap(coin(asset.amount.u128(), denom.clone()), "uusd")?
+            .receive
+            .amount),
+        AssetInfo::Token { contract_addr } if *contract_addr == state.aust_token_address => {
+            let price = get_fresh_price(
+                deps,
+                ctx,
+                &state.oracle_hub_contract,
+                contract_addr.to_string(),
+            )?
+            .rate;
+
+            Ok(asset.amount * price)
+        }
+        AssetInfo::Token { .. } => {
+            let pair_contract =
+                ctx.pair_address(&deps.querier, &state.astroport_factory_address, &asset.info)?;
+
+            Ok(simulate(&deps.querier, pair_contract, asset)?.return_amount)
+        }
+    }
 }
 
-pub fn get_price(deps: Deps, contract: &Addr, asset_token: String) -> StdResult<PriceResponse> {
-    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-        contract_addr: contract.to_string(),
-        msg: to_binary(&QueryMsgOracleHub::Price {
-            asset_token,
-            timeframe: None,
-        })?,
-    }))
+/// ## Description
+/// Returns the UST value of the spread and commission paid to buy an asset.
+fn fees_in_ust(simulation: &SimulateMintAsset) -> Uint128 {
+    let fees = simulation.spread_amount + simulation.commission_amount;
+    if fees.is_zero() {
+        return Uint128::zero();
+    }
+
+    simulation
+        .ust_amount
+        .multiply_ratio(fees, simulation.return_amount + fees)
 }
 
-pub fn get_penalty_query_create(
+/// ## Description
+/// Queries the penalty contract of a cluster for the cluster tokens minted with the given assets.
+pub fn query_create_tokens(
     deps: Deps,
-    contract: &Addr,
     block_height: u64,
-    cluster_token_supply: Uint128,
-    inventory: Vec<Uint128>,
+    cluster_state: &ClusterStateResponse,
     create_asset_amounts: Vec<Uint128>,
-    asset_prices: Vec<String>,
-    target_weights: Vec<Uint128>,
 ) -> StdResult<PenaltyCreateResponse> {
-    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-        contract_addr: contract.to_string(),
-        msg: to_binary(&QueryMsgPenalty::PenaltyQueryCreate {
-            block_height,
-            cluster_token_supply,
-            inventory,
-            create_asset_amounts,
-            asset_prices,
-            target_weights,
-        })?,
-    }))
+    PenaltyContract(deps.api.addr_validate(cluster_state.penalty.as_ref())?).create(
+        &deps.querier,
+        block_height,
+        cluster_state,
+        create_asset_amounts,
+    )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn simulate_mint(
     deps: Deps,
     env: Env,
     cluster_address: String,
     ust_amt: Uint128,
-) -> StdResult<SimulateMintResponse> {
+    allocation_mode: AllocationMode,
+    block_height: Option<u64>,
+    max_price_age: Option<u64>,
+    referral_bps: Option<u16>,
+    in_kind: Vec<Asset>,
+) -> Result<SimulateMintResponse, ContractError> {
     let state = STATE.load(deps.storage)?;
-    let validated_cluster_address = deps.api.addr_validate(cluster_address.as_ref())?;
-    let cluster_state = get_cluster_state(deps, &validated_cluster_address)?;
-    let total_target_weight: Uint128 = cluster_state.target.clone().iter().map(|x| x.amount).sum();
-    let mut create_asset_amounts: Vec<Uint128> = vec![];
-    let terra_querier = TerraQuerier::new(&deps.querier);
+    let referral_bps = referral_bps.unwrap_or(0);
+    if referral_bps > state.max_referral_bps {
+        return Err(ContractError::ReferralFeeTooHigh {
+            max_bps: state.max_referral_bps,
+        });
+    }
+    let cluster_state = load_cluster_state(deps, &cluster_address)?;
+    assert_in_kind(&cluster_address, &cluster_state, &in_kind)?;
+    let ctx = SimulationContext::new(
+        block_height.unwrap_or(env.block.height),
+        env.block.time.seconds(),
+        max_price_age,
+    );
 
-    for asset in cluster_state.target.clone() {
-        let asset_ratio = ust_amt * asset.amount / total_target_weight;
+    Ok(simulate_cluster_mint_in_kind(
+        deps,
+        &ctx,
+        &state,
+        &cluster_state,
+        ust_amt,
+        &allocation_mode,
+        referral_bps,
+        &in_kind,
+    )?)
+}
 
-        match asset.info.clone() {
-            AssetInfo::NativeToken { denom } => {
-                if denom == "uusd" {
-                    create_asset_amounts.push(asset_ratio)
-                } else {
-                    let return_amount = terra_querier
-                        .query_swap(coin(asset_ratio.u128(), "uusd"), denom)?
-                        .receive
-                        .amount;
+pub fn query_clusters(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ClustersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|cluster| Bound::exclusive(cluster.as_bytes()));
 
-                    create_asset_amounts.push(return_amount)
-                }
-            }
-            AssetInfo::Token { contract_addr } => {
-                if contract_addr != state.aust_token_address {
-                    let pair_contract = query_pair_info(
-                        &deps.querier,
-                        state.astroport_factory_address.clone(),
-                        &[
-                            asset.info.clone(),
-                            AssetInfo::NativeToken {
-                                denom: "uusd".to_string(),
-                            },
-                        ],
-                    )?
-                    .contract_addr;
+    let clusters = ALLOWED_CLUSTERS
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| String::from_utf8(key).map_err(StdError::from))
+        .collect::<StdResult<Vec<String>>>()?;
 
-                    let return_amount = simulate(
-                        &deps.querier,
-                        pair_contract,
-                        &Asset {
-                            info: AssetInfo::NativeToken {
-                                denom: "uusd".to_string(),
-                            },
-                            amount: asset_ratio,
-                        },
-                    )?
-                    .return_amount;
+    Ok(ClustersResponse { clusters })
+}
 
-                    create_asset_amounts.push(return_amount)
-                } else {
-                    let price = get_price(
-                        deps,
-                        &state.oracle_hub_contract.clone(),
-                        contract_addr.to_string(),
-                    )?
-                    .rate;
+pub fn query_mint_capacity(
+    deps: Deps,
+    env: Env,
+    cluster_address: String,
+) -> StdResult<MintCapacityResponse> {
+    let state = STATE.load(deps.storage)?;
+    let cluster = deps.api.addr_validate(cluster_address.as_ref())?;
+    let block_time = env.block.time.seconds();
+    let (window_used, _) = window_usage(deps.storage, &state, &cluster, block_time)?;
+    let window_remaining = state
+        .cluster_window_cap
+        .map(|cap| cap.saturating_sub(window_used));
+    let remaining = match (state.max_ust_per_mint, window_remaining) {
+        (Some(per_mint), Some(window)) => Some(std::cmp::min(per_mint, window)),
+        (per_mint, window) => per_mint.or(window),
+    };
 
-                    let return_amount =
-                        asset_ratio.multiply_ratio(DECIMAL_FRACTIONAL, price * DECIMAL_FRACTIONAL);
+    Ok(MintCapacityResponse {
+        max_ust_per_mint: state.max_ust_per_mint,
+        cluster_window_cap: state.cluster_window_cap,
+        window_start: block_time.saturating_sub(state.cap_window_seconds),
+        window_end: block_time,
+        window_used,
+        remaining,
+    })
+}
 
-                    create_asset_amounts.push(return_amount)
-                }
+pub fn query_status(deps: Deps) -> StdResult<StatusResponse> {
+    let state = STATE.load(deps.storage)?;
+
+    Ok(StatusResponse {
+        paused: state.paused,
+        guardian: state.guardian.to_string(),
+    })
+}
+
+/// ## Description
+/// Returns the completed mints of `user`, into `cluster` or both, newest first.
+/// Without a user or a cluster every mint is returned.
+pub fn query_mint_history(
+    deps: Deps,
+    user: Option<String>,
+    cluster: Option<String>,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<MintHistoryResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let end = start_after.map(|id| Bound::exclusive(id.to_be_bytes().to_vec()));
+    let user = user
+        .map(|user| deps.api.addr_validate(user.as_ref()))
+        .transpose()?;
+    let cluster = cluster
+        .map(|cluster| deps.api.addr_validate(cluster.as_ref()))
+        .transpose()?;
+
+    let history = mint_history();
+    let records = match (&user, &cluster) {
+        (Some(user), _) => history.idx.user.prefix(user.as_bytes().to_vec()).range(
+            deps.storage,
+            None,
+            end,
+            Order::Descending,
+        ),
+        (None, Some(cluster)) => history
+            .idx
+            .cluster
+            .prefix(cluster.as_bytes().to_vec())
+            .range(deps.storage, None, end, Order::Descending),
+        (None, None) => history.range(deps.storage, None, end, Order::Descending),
+    };
+
+    let mints = records
+        .filter(|item| match (item, &cluster) {
+            (Ok((_, record)), Some(cluster)) => record.cluster == *cluster,
+            _ => true,
+        })
+        .take(limit)
+        .map(|item| {
+            let (key, record) = item?;
+            let id = u64::from_be_bytes(
+                key.as_slice()
+                    .try_into()
+                    .map_err(|_| StdError::generic_err("invalid mint record key"))?,
+            );
+
+            Ok(MintRecordResponse {
+                id,
+                user: record.user.to_string(),
+                cluster: record.cluster.to_string(),
+                ust_in: record.ust_in,
+                cluster_tokens_out: record.cluster_tokens_out,
+                block_height: record.block_height,
+                block_time: record.block_time,
+            })
+        })
+        .collect::<StdResult<Vec<MintRecordResponse>>>()?;
+
+    Ok(MintHistoryResponse { mints })
+}
+
+pub fn query_cluster_volume(
+    deps: Deps,
+    cluster_address: String,
+) -> StdResult<ClusterVolumeResponse> {
+    let volume = CLUSTER_VOLUMES
+        .may_load(
+            deps.storage,
+            &deps.api.addr_validate(cluster_address.as_ref())?,
+        )?
+        .unwrap_or_default();
+
+    Ok(ClusterVolumeResponse {
+        cluster_address,
+        ust_in: volume.ust_in,
+        cluster_tokens_out: volume.cluster_tokens_out,
+        mints: volume.mints,
+    })
+}
+
+pub fn query_referral_stats(deps: Deps, address: String) -> StdResult<ReferralStatsResponse> {
+    let stats = REFERRAL_STATS
+        .may_load(deps.storage, &deps.api.addr_validate(address.as_ref())?)?
+        .unwrap_or_default();
+
+    Ok(ReferralStatsResponse {
+        address,
+        volume: stats.volume,
+        fees: stats.fees,
+        mints: stats.mints,
+    })
+}
+
+pub fn simulate_mint_batch(
+    deps: Deps,
+    env: Env,
+    requests: Vec<(String, Uint128)>,
+    allocation_mode: AllocationMode,
+) -> StdResult<SimulateMintBatchResponse> {
+    let state = STATE.load(deps.storage)?;
+    let ctx = SimulationContext::from_env(&env);
+
+    let results = requests
+        .into_iter()
+        .map(|(cluster_address, ust_amount)| {
+            let simulation = load_cluster_state(deps, &cluster_address).and_then(|cluster_state| {
+                Ok(simulate_cluster_mint(
+                    deps,
+                    &ctx,
+                    &state,
+                    &cluster_state,
+                    ust_amount,
+                    &allocation_mode,
+                    0,
+                )?)
+            });
+
+            let (simulation, error) = match simulation {
+                Ok(simulation) => (Some(simulation), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            SimulateMintBatchResult {
+                cluster_address,
+                ust_amount,
+                simulation,
+                error,
             }
+        })
+        .collect();
+
+    Ok(SimulateMintBatchResponse { results })
+}
+
+/// ## Description
+/// Simulates a `MintMany` of `ust_amt`, buying the assets shared between the clusters once.
+pub fn simulate_mint_many(
+    deps: Deps,
+    env: Env,
+    allocations: Vec<(String, Uint128)>,
+    ust_amt: Uint128,
+    allocation_mode: AllocationMode,
+) -> Result<SimulateMintManyResponse, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let ctx = SimulationContext::from_env(&env);
+    let plans = plan_mint_many(deps, &ctx, &state, &allocations, ust_amt, &allocation_mode)?;
+
+    let baskets: Vec<Vec<Asset>> = plans.iter().map(|plan| plan.basket()).collect();
+    let assets = aggregate_assets(&baskets)?
+        .iter()
+        .map(|asset| simulate_asset(deps, &ctx, &state, &asset.info, asset.amount))
+        .collect::<StdResult<Vec<SimulateMintAsset>>>()?;
+    let bought: Vec<Asset> = assets
+        .iter()
+        .map(|asset| Asset {
+            info: asset.info.clone(),
+            amount: asset.return_amount,
+        })
+        .collect();
+
+    let mints = plans
+        .iter()
+        .zip(split_assets(&baskets, &bought)?)
+        .map(|(plan, share)| {
+            let create_asset_amounts: Vec<Uint128> = plan
+                .cluster_state
+                .target
+                .iter()
+                .map(|target| {
+                    share
+                        .iter()
+                        .find(|asset| asset.info == target.info)
+                        .map(|asset| asset.amount)
+                        .unwrap_or_default()
+                })
+                .collect();
+            let penalty = query_create_tokens(
+                deps,
+                ctx.block_height,
+                &plan.cluster_state,
+                create_asset_amounts.clone(),
+            )?;
+
+            Ok(SimulateMintManyResult {
+                cluster_address: plan.cluster.to_string(),
+                ust_amount: plan.ust_in,
+                create_tokens: penalty.create_tokens,
+                penalty: penalty.penalty,
+                create_asset_amounts,
+                protocol_fee: plan.protocol_fee,
+            })
+        })
+        .collect::<StdResult<Vec<SimulateMintManyResult>>>()?;
+
+    Ok(SimulateMintManyResponse {
+        protocol_fee: plans.iter().map(|plan| plan.protocol_fee).sum(),
+        mints,
+        assets,
+    })
+}
+
+/// ## Description
+/// Simulates a `SwitchCluster` of `amount` cluster tokens of `from_cluster` into `to_cluster`.
+pub fn simulate_switch_cluster(
+    deps: Deps,
+    env: Env,
+    from_cluster: String,
+    to_cluster: String,
+    amount: Uint128,
+) -> Result<SimulateSwitchClusterResponse, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let ctx = SimulationContext::from_env(&env);
+    let from_state = load_cluster_state(deps, &from_cluster)?;
+    let to_state = load_cluster_state(deps, &to_cluster)?;
+    if from_state.cluster_contract_address == to_state.cluster_contract_address {
+        return Err(ContractError::SameCluster {
+            cluster: to_cluster,
+        });
+    }
+    if amount.is_zero() || amount > from_state.outstanding_balance_tokens {
+        return Err(StdError::generic_err(
+            "Cluster token amount must be positive and at most the supply",
+        )
+        .into());
+    }
+
+    // a pro rata redeem returns the same share of every asset of the inventory
+    let redeem_assets: Vec<Asset> = from_state
+        .target
+        .iter()
+        .zip(from_state.inv.iter())
+        .map(|(asset, inv)| Asset {
+            info: asset.info.clone(),
+            amount: inv.multiply_ratio(amount, from_state.outstanding_balance_tokens),
+        })
+        .collect();
+    let plan = plan_switch(&redeem_assets, &from_state, &to_state)?;
+
+    let ust_amount =
+        plan.sells
+            .iter()
+            .try_fold(Uint128::zero(), |total, asset| -> StdResult<Uint128> {
+                let sale = simulate_sale(deps, &ctx, &state, asset)?;
+                Ok(total.checked_add(sale)?)
+            })?;
+    let protocol_fee = compute_protocol_fee(&state, ust_amount);
+    let weights: Vec<Uint128> = plan.deficits.iter().map(|asset| asset.amount).collect();
+    let bought = if weights.iter().all(|weight| weight.is_zero()) {
+        vec![]
+    } else {
+        plan.deficits
+            .iter()
+            .zip(split_by_weights(ust_amount - protocol_fee, &weights)?)
+            .map(|(asset, ust_amount)| simulate_asset(deps, &ctx, &state, &asset.info, ust_amount))
+            .collect::<StdResult<Vec<SimulateMintAsset>>>()?
+    };
+
+    let create_asset_amounts: Vec<Uint128> = to_state
+        .target
+        .iter()
+        .map(|target| {
+            let kept = amount_of(&redeem_assets, &target.info)
+                .saturating_sub(amount_of(&plan.sells, &target.info));
+            let bought = bought
+                .iter()
+                .find(|asset| asset.info == target.info)
+                .map(|asset| asset.return_amount)
+                .unwrap_or_default();
+
+            kept + bought
+        })
+        .collect();
+    let penalty = query_create_tokens(
+        deps,
+        ctx.block_height,
+        &to_state,
+        create_asset_amounts.clone(),
+    )?;
+
+    Ok(SimulateSwitchClusterResponse {
+        redeem_assets,
+        sold: plan.sells,
+        ust_amount,
+        protocol_fee,
+        bought,
+        create_tokens: penalty.create_tokens,
+        penalty: penalty.penalty,
+        create_asset_amounts,
+    })
+}
+
+pub fn simulate_mint_exact(
+    deps: Deps,
+    env: Env,
+    cluster_address: String,
+    cluster_token_amount: Uint128,
+    allocation_mode: AllocationMode,
+    tolerance: Decimal,
+) -> Result<SimulateMintExactResponse, ContractError> {
+    let state = STATE.load(deps.storage)?;
+    let cluster_state = load_cluster_state(deps, &cluster_address)?;
+
+    let (ust_amount, simulation) = find_mint_ust_amount(
+        deps,
+        &SimulationContext::from_env(&env),
+        &state,
+        &cluster_state,
+        cluster_token_amount,
+        &allocation_mode,
+        tolerance,
+    )?;
+
+    Ok(SimulateMintExactResponse {
+        ust_amount,
+        simulation,
+    })
+}
+
+/// ## Description
+/// Searches the smallest UST amount that mints at least `cluster_token_amount` cluster tokens,
+/// up to a relative `tolerance`. Returns the UST amount along with its simulation.
+pub fn find_mint_ust_amount(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    cluster_state: &ClusterStateResponse,
+    cluster_token_amount: Uint128,
+    allocation_mode: &AllocationMode,
+    tolerance: Decimal,
+) -> StdResult<(Uint128, SimulateMintResponse)> {
+    if cluster_token_amount.is_zero() {
+        return Err(StdError::generic_err(
+            "Cluster token amount must be positive",
+        ));
+    }
+
+    let probe = simulate_cluster_mint(
+        deps,
+        ctx,
+        state,
+        cluster_state,
+        SEARCH_PROBE_AMOUNT,
+        allocation_mode,
+        0,
+    )?;
+    if probe.create_tokens.is_zero() {
+        return Err(StdError::generic_err("Cluster does not mint any token"));
+    }
+
+    // grow the linear estimate until it mints enough, then bisect down to the tolerance
+    let mut iterations = 0;
+    let mut low = Uint128::zero();
+    let mut high = std::cmp::max(
+        SEARCH_PROBE_AMOUNT.multiply_ratio(cluster_token_amount, probe.create_tokens),
+        Uint128::new(1),
+    );
+    let mut high_simulation = loop {
+        let simulation =
+            simulate_cluster_mint(deps, ctx, state, cluster_state, high, allocation_mode, 0)?;
+        if simulation.create_tokens >= cluster_token_amount {
+            break simulation;
+        }
+
+        iterations += 1;
+        if iterations >= MAX_SEARCH_ITERATIONS {
+            return Err(StdError::generic_err(
+                "Cannot find a UST amount minting the cluster token amount",
+            ));
+        }
+        low = high;
+        high = high.checked_add(high)?;
+    };
+
+    while high - low > high * tolerance && iterations < MAX_SEARCH_ITERATIONS {
+        let middle = low + (high - low).multiply_ratio(1u128, 2u128);
+        if middle == low {
+            break;
+        }
+
+        let simulation =
+            simulate_cluster_mint(deps, ctx, state, cluster_state, middle, allocation_mode, 0)?;
+        if simulation.create_tokens >= cluster_token_amount {
+            high = middle;
+            high_simulation = simulation;
+        } else {
+            low = middle;
         }
+        iterations += 1;
     }
 
-    let penalty = get_penalty_query_create(
+    Ok((high, high_simulation))
+}
+
+/// ## Description
+/// Simulates minting the cluster token of `cluster_state` with `ust_amt`.
+pub fn simulate_cluster_mint(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    cluster_state: &ClusterStateResponse,
+    ust_amt: Uint128,
+    allocation_mode: &AllocationMode,
+    referral_bps: u16,
+) -> StdResult<SimulateMintResponse> {
+    simulate_cluster_mint_in_kind(
+        deps,
+        ctx,
+        state,
+        cluster_state,
+        ust_amt,
+        allocation_mode,
+        referral_bps,
+        &[],
+    )
+}
+
+/// ## Description
+/// Simulates minting the cluster token of `cluster_state` with `ust_amt` and the `in_kind`
+/// target assets, the UST only buying what the in-kind assets fall short of target.
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_cluster_mint_in_kind(
+    deps: Deps,
+    ctx: &SimulationContext,
+    state: &State,
+    cluster_state: &ClusterStateResponse,
+    ust_amt: Uint128,
+    allocation_mode: &AllocationMode,
+    referral_bps: u16,
+    in_kind: &[Asset],
+) -> StdResult<SimulateMintResponse> {
+    let referral_fee = ust_amt.multiply_ratio(referral_bps, BPS_DENOMINATOR);
+    let protocol_fee = compute_protocol_fee(state, ust_amt - referral_fee);
+    let allocation = mint_allocation(
+        deps,
+        ctx,
+        state,
+        cluster_state,
+        ust_amt - referral_fee - protocol_fee,
+        allocation_mode,
+        in_kind,
+    )?;
+    let assets = simulate_assets(deps, ctx, state, &cluster_state.target, &allocation)?;
+    let create_asset_amounts: Vec<Uint128> = assets
+        .iter()
+        .map(|asset| asset.return_amount + amount_of(in_kind, &asset.info))
+        .collect();
+
+    let penalty = query_create_tokens(
         deps,
-        &deps.api.addr_validate(cluster_state.penalty.as_ref())?,
-        env.block.height,
-        cluster_state.outstanding_balance_tokens,
-        cluster_state.inv,
+        ctx.block_height,
+        cluster_state,
         create_asset_amounts.clone(),
-        cluster_state.prices,
-        cluster_state
-            .target
-            .iter()
-            .map(|asset| asset.amount)
-            .collect(),
     )?;
+    // the in-kind assets are paid for at the prices of the cluster
+    let in_kind_value =
+        in_kind
+            .iter()
+            .try_fold(Uint128::zero(), |total, asset| -> StdResult<Uint128> {
+                let value = asset.amount * cluster_price(cluster_state, &asset.info)?;
+                Ok(total.checked_add(value)?)
+            })?;
 
     Ok(SimulateMintResponse {
         create_tokens: penalty.create_tokens,
         penalty: penalty.penalty,
         attributes: penalty.attributes,
         create_asset_amounts,
+        referral_fee,
+        protocol_fee,
+        total_fees: assets.iter().map(fees_in_ust).sum(),
+        effective_price: if penalty.create_tokens.is_zero() {
+            Decimal::zero()
+        } else {
+            Decimal::from_ratio(ust_amt.checked_add(in_kind_value)?, penalty.create_tokens)
+        },
+        assets,
     })
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{Decimal, StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +8,64 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Contract is paused")]
+    Paused {},
+
+    #[error("Cluster {cluster} is not allowed")]
+    ClusterNotAllowed { cluster: String },
+
+    #[error("Cluster {cluster} is not registered in the cluster factory")]
+    ClusterNotRegistered { cluster: String },
+
+    #[error("Cluster token {cluster_token} is not registered for cluster {cluster}")]
+    ClusterTokenMismatch {
+        cluster: String,
+        cluster_token: String,
+    },
+
+    #[error("Fee exceeds the maximum of {max_bps} basis points")]
+    FeeTooHigh { max_bps: u16 },
+
+    #[error("Referral share exceeds the maximum of {max_bps} basis points")]
+    ReferralFeeTooHigh { max_bps: u16 },
+
+    #[error("Mint exceeds the maximum of {max} UST")]
+    MintCapExceeded { max: Uint128 },
+
+    #[error("Mint exceeds the cap of cluster {cluster}, {remaining} UST remaining")]
+    WindowCapExceeded { cluster: String, remaining: Uint128 },
+
+    #[error("Price of {asset} deviates {deviation} from the oracle")]
+    PriceDeviation { asset: String, deviation: Decimal },
+
+    #[error("Oracle price of {asset} rounds to zero")]
+    ZeroOraclePrice { asset: String },
+
+    #[error("Insufficient UST sent, {required} required")]
+    InsufficientFunds { required: Uint128 },
+
+    #[error("Cluster {cluster} is listed more than once")]
+    DuplicateCluster { cluster: String },
+
+    #[error("Token {cluster_token} is not the token of an allowed cluster")]
+    UnknownClusterToken { cluster_token: String },
+
+    #[error("Cannot switch cluster {cluster} into itself")]
+    SameCluster { cluster: String },
+
+    #[error("Asset {asset} is not in cluster {cluster}")]
+    AssetNotInCluster { asset: String, cluster: String },
+
+    #[error("Quote does not match the {assets} target assets of cluster {cluster}")]
+    QuoteMismatch { cluster: String, assets: usize },
+
+    #[error("Received {received} {asset}, below the quoted minimum of {min}")]
+    QuoteNotMet {
+        asset: String,
+        received: Uint128,
+        min: Uint128,
+    },
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -0,0 +1,397 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use astroport::asset::Asset;
+use cosmwasm_std::{
+    to_binary, Addr, Coin, CosmosMsg, Decimal, QuerierWrapper, StdResult, Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+
+use crate::msg::{
+    AllocationMode, ClusterVolumeResponse, ClustersResponse, Cw20HookMsg, ExecuteMsg,
+    MintCapacityResponse, MintHistoryResponse, MintQuote, QueryMsg, ReferralStatsResponse,
+    SimulateMintBatchResponse, SimulateMintExactResponse, SimulateMintManyResponse,
+    SimulateMintResponse, SimulateSwitchClusterResponse, StatusResponse,
+};
+
+/// ## Description
+/// Optional parameters of `ExecuteMsg::MintCT` and `QueryMsg::SimulateMint`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MintOptions {
+    /// How to split the UST between the cluster assets, defaults to `Target`
+    pub allocation: Option<AllocationMode>,
+    /// Integrator credited with the mint
+    pub referrer: Option<String>,
+    /// Share of the UST sent to the referrer in basis points
+    pub referral_bps: Option<u16>,
+    /// Quote of a prior simulation the mint must honour, ignored by simulations
+    pub quote: Option<MintQuote>,
+}
+
+/// ## Description
+/// A wrapper around the address of a deployed mint utils contract, building its
+/// messages and running its queries. Generic over the custom message type, so it can be
+/// used from contracts returning `TerraMsgWrapper` as well as plain ones.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintUtilsContract(pub Addr);
+
+impl MintUtilsContract {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /// ## Description
+    /// Returns a message executing `msg` on the contract with `funds`.
+    pub fn call<T>(&self, msg: ExecuteMsg, funds: Vec<Coin>) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        Ok(WasmMsg::Execute {
+            contract_addr: self.addr().into(),
+            msg: to_binary(&msg)?,
+            funds,
+        }
+        .into())
+    }
+
+    /// ## Description
+    /// Returns a message minting the cluster token of `cluster` with the UST in `funds`.
+    pub fn mint_ct_msg<T>(
+        &self,
+        cluster: impl Into<String>,
+        funds: Vec<Coin>,
+        opts: MintOptions,
+    ) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        self.call(
+            ExecuteMsg::MintCT {
+                cluster_address: cluster.into(),
+                allocation: opts.allocation,
+                referrer: opts.referrer,
+                referral_bps: opts.referral_bps,
+                quote: opts.quote,
+            },
+            funds,
+        )
+    }
+
+    /// ## Description
+    /// Returns a message minting `cluster_token_amount` cluster tokens of `cluster`,
+    /// refunding the UST of `funds` left over.
+    pub fn mint_ct_exact_msg<T>(
+        &self,
+        cluster: impl Into<String>,
+        cluster_token_amount: Uint128,
+        funds: Vec<Coin>,
+        allocation: Option<AllocationMode>,
+        tolerance: Option<Decimal>,
+    ) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        self.call(
+            ExecuteMsg::MintCTExact {
+                cluster_address: cluster.into(),
+                cluster_token_amount,
+                allocation,
+                tolerance,
+            },
+            funds,
+        )
+    }
+
+    /// ## Description
+    /// Returns a message splitting the UST in `funds` between the clusters of `allocations`
+    /// by weight and minting each cluster token.
+    pub fn mint_many_msg<T>(
+        &self,
+        allocations: Vec<(String, Uint128)>,
+        min_outputs: Option<Vec<Uint128>>,
+        funds: Vec<Coin>,
+        allocation: Option<AllocationMode>,
+    ) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        self.call(
+            ExecuteMsg::MintMany {
+                allocations,
+                min_outputs,
+                allocation,
+            },
+            funds,
+        )
+    }
+
+    /// ## Description
+    /// Returns a message sending `amount` of `cluster_token` to the contract to switch them
+    /// into the cluster token of `to_cluster`.
+    pub fn switch_cluster_msg<T>(
+        &self,
+        cluster_token: impl Into<String>,
+        amount: Uint128,
+        to_cluster: impl Into<String>,
+        min_out: Option<Uint128>,
+    ) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        Ok(WasmMsg::Execute {
+            contract_addr: cluster_token.into(),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: self.addr().into(),
+                amount,
+                msg: to_binary(&Cw20HookMsg::SwitchCluster {
+                    to_cluster: to_cluster.into(),
+                    min_out,
+                })?,
+            })?,
+            funds: vec![],
+        }
+        .into())
+    }
+
+    /// ## Description
+    /// Sends `amount` of the cw20 `token` to the contract to be provided in kind when minting
+    /// the cluster token of `cluster`.
+    pub fn mint_ct_in_kind_msg<T>(
+        &self,
+        token: impl Into<String>,
+        amount: Uint128,
+        cluster: impl Into<String>,
+        opts: MintOptions,
+    ) -> StdResult<CosmosMsg<T>>
+    where
+        T: Clone + fmt::Debug + PartialEq + JsonSchema,
+    {
+        Ok(WasmMsg::Execute {
+            contract_addr: token.into(),
+            msg: to_binary(&Cw20ExecuteMsg::Send {
+                contract: self.addr().into(),
+                amount,
+                msg: to_binary(&Cw20HookMsg::MintCT {
+                    cluster_address: cluster.into(),
+                    allocation: opts.allocation,
+                    referrer: opts.referrer,
+                    referral_bps: opts.referral_bps,
+                    quote: opts.quote,
+                })?,
+            })?,
+            funds: vec![],
+        }
+        .into())
+    }
+
+    /// ## Description
+    /// Runs `msg` against the contract.
+    pub fn query<T: DeserializeOwned>(
+        &self,
+        querier: &QuerierWrapper,
+        msg: &QueryMsg,
+    ) -> StdResult<T> {
+        querier.query_wasm_smart(self.addr(), msg)
+    }
+
+    /// ## Description
+    /// Simulates minting the cluster token of `cluster` with `ust_amount` and the default options.
+    pub fn simulate_mint(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+        ust_amount: Uint128,
+    ) -> StdResult<SimulateMintResponse> {
+        self.simulate_mint_with(querier, cluster, ust_amount, MintOptions::default())
+    }
+
+    /// ## Description
+    /// Simulates minting the cluster token of `cluster` with `ust_amount`. Only the
+    /// referral share of `opts` is simulated, not the referrer.
+    pub fn simulate_mint_with(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+        ust_amount: Uint128,
+        opts: MintOptions,
+    ) -> StdResult<SimulateMintResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateMint {
+                cluster_address: cluster.into(),
+                ust_amount,
+                allocation: opts.allocation,
+                block_height: None,
+                max_price_age: None,
+                referral_bps: opts.referral_bps,
+                in_kind: None,
+            },
+        )
+    }
+
+    /// ## Description
+    /// Simulates minting the cluster token of `cluster` with `ust_amount` and the `in_kind`
+    /// target assets.
+    pub fn simulate_mint_in_kind(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+        ust_amount: Uint128,
+        in_kind: Vec<Asset>,
+    ) -> StdResult<SimulateMintResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateMint {
+                cluster_address: cluster.into(),
+                ust_amount,
+                allocation: None,
+                block_height: None,
+                max_price_age: None,
+                referral_bps: None,
+                in_kind: Some(in_kind),
+            },
+        )
+    }
+
+    pub fn simulate_mint_exact(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+        cluster_token_amount: Uint128,
+        allocation: Option<AllocationMode>,
+    ) -> StdResult<SimulateMintExactResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateMintExact {
+                cluster_address: cluster.into(),
+                cluster_token_amount,
+                allocation,
+                tolerance: None,
+            },
+        )
+    }
+
+    pub fn simulate_mint_batch(
+        &self,
+        querier: &QuerierWrapper,
+        requests: Vec<(String, Uint128)>,
+        allocation: Option<AllocationMode>,
+    ) -> StdResult<SimulateMintBatchResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateMintBatch {
+                requests,
+                allocation,
+            },
+        )
+    }
+
+    pub fn simulate_mint_many(
+        &self,
+        querier: &QuerierWrapper,
+        allocations: Vec<(String, Uint128)>,
+        ust_amount: Uint128,
+        allocation: Option<AllocationMode>,
+    ) -> StdResult<SimulateMintManyResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateMintMany {
+                allocations,
+                ust_amount,
+                allocation,
+            },
+        )
+    }
+
+    pub fn simulate_switch_cluster(
+        &self,
+        querier: &QuerierWrapper,
+        from_cluster: impl Into<String>,
+        to_cluster: impl Into<String>,
+        amount: Uint128,
+    ) -> StdResult<SimulateSwitchClusterResponse> {
+        self.query(
+            querier,
+            &QueryMsg::SimulateSwitchCluster {
+                from_cluster: from_cluster.into(),
+                to_cluster: to_cluster.into(),
+                amount,
+            },
+        )
+    }
+
+    pub fn clusters(
+        &self,
+        querier: &QuerierWrapper,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    ) -> StdResult<ClustersResponse> {
+        self.query(querier, &QueryMsg::Clusters { start_after, limit })
+    }
+
+    pub fn mint_capacity(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+    ) -> StdResult<MintCapacityResponse> {
+        self.query(
+            querier,
+            &QueryMsg::MintCapacity {
+                cluster_address: cluster.into(),
+            },
+        )
+    }
+
+    pub fn status(&self, querier: &QuerierWrapper) -> StdResult<StatusResponse> {
+        self.query(querier, &QueryMsg::Status {})
+    }
+
+    pub fn referral_stats(
+        &self,
+        querier: &QuerierWrapper,
+        address: impl Into<String>,
+    ) -> StdResult<ReferralStatsResponse> {
+        self.query(
+            querier,
+            &QueryMsg::ReferralStats {
+                address: address.into(),
+            },
+        )
+    }
+
+    /// ## Description
+    /// Returns the completed mints of `user`, into `cluster` or both, newest first.
+    pub fn mint_history(
+        &self,
+        querier: &QuerierWrapper,
+        user: Option<String>,
+        cluster: Option<String>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    ) -> StdResult<MintHistoryResponse> {
+        self.query(
+            querier,
+            &QueryMsg::MintHistory {
+                user,
+                cluster,
+                start_after,
+                limit,
+            },
+        )
+    }
+
+    pub fn cluster_volume(
+        &self,
+        querier: &QuerierWrapper,
+        cluster: impl Into<String>,
+    ) -> StdResult<ClusterVolumeResponse> {
+        self.query(
+            querier,
+            &QueryMsg::ClusterVolume {
+                cluster_address: cluster.into(),
+            },
+        )
+    }
+}
@@ -1,8 +1,10 @@
 use cosmwasm_std::{Attribute, Decimal, Uint128};
+use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-use astroport::asset::Asset;
+use astroport::asset::{Asset, AssetInfo};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
@@ -12,14 +14,89 @@ pub struct InstantiateMsg {
     pub anchor_market_contract: String,
     pub oracle_hub_contract: String,
     pub owner_address: String,
+    /// Receiver of the protocol fee, defaults to the sender
+    pub fee_collector: Option<String>,
+    /// Protocol fee taken from the UST of each mint in basis points, defaults to 0
+    pub protocol_fee_bps: Option<u16>,
+    /// Maximum share of a mint a referrer can receive in basis points, defaults to 0
+    pub max_referral_bps: Option<u16>,
+    /// Address allowed to pause the contract besides the owner, defaults to the sender
+    pub guardian: Option<String>,
+    /// Nebula cluster factory used to verify cluster tokens, unchecked when `None`
+    pub cluster_factory: Option<String>,
+    /// Maximum deviation of Astroport fills from the oracle hub price, unchecked when `None`
+    pub max_price_deviation: Option<Decimal>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
+    /// Receives cluster tokens with a [`Cw20HookMsg`]
+    Receive(Cw20ReceiveMsg),
+    /// Owner operation to update the contract settings
+    UpdateConfig {
+        /// Protocol fee in basis points
+        protocol_fee_bps: Option<u16>,
+        /// Receiver of the protocol fee
+        fee_collector: Option<String>,
+        /// Maximum referral share in basis points
+        max_referral_bps: Option<u16>,
+        /// Address allowed to pause the contract besides the owner
+        guardian: Option<String>,
+        /// Nebula cluster factory used to verify cluster tokens
+        cluster_factory: Option<String>,
+        /// Maximum UST per mint, 0 removes the cap
+        max_ust_per_mint: Option<Uint128>,
+        /// Maximum UST minted per cluster in any rolling window, 0 removes the cap
+        cluster_window_cap: Option<Uint128>,
+        /// Length of the per-cluster rolling cap window in seconds
+        cap_window_seconds: Option<u64>,
+        /// Maximum deviation of Astroport fills from the oracle hub price, 0 removes the check
+        max_price_deviation: Option<Decimal>,
+    },
+    /// Owner operation to allow minting a cluster
+    AddCluster { cluster_address: String },
+    /// Owner operation to disallow minting a cluster
+    RemoveCluster { cluster_address: String },
+    /// Owner or guardian operation to reject every other execute message
+    Pause {},
+    /// Owner or guardian operation to accept execute messages again
+    Unpause {},
+    /// Mints the cluster token with the UST sent. Target assets sent along are provided in
+    /// kind and the UST only buys what they fall short of target
     MintCT {
         /// Cluster contract address
         cluster_address: String,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+        /// Integrator credited with the mint
+        referrer: Option<String>,
+        /// Share of the UST sent to the referrer in basis points
+        referral_bps: Option<u16>,
+        /// Quote of a prior `SimulateMint` the assets received must honour
+        quote: Option<MintQuote>,
+    },
+    /// Mints `cluster_token_amount` cluster tokens and refunds the UST left over. Only UST
+    /// can be sent
+    MintCTExact {
+        /// Cluster contract address
+        cluster_address: String,
+        /// Amount of cluster tokens to mint
+        cluster_token_amount: Uint128,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+        /// Relative precision of the UST amount search, defaults to 0.1%
+        tolerance: Option<Decimal>,
+    },
+    /// Splits the UST sent between several clusters by weight and mints each cluster token,
+    /// buying the assets shared between the clusters once
+    MintMany {
+        /// Cluster contract addresses with their weight
+        allocations: Vec<(String, Uint128)>,
+        /// Minimum cluster tokens minted per cluster, in the order of `allocations`
+        min_outputs: Option<Vec<Uint128>>,
+        /// How to split the UST between the assets of each cluster, defaults to `Target`
+        allocation: Option<AllocationMode>,
     },
     _MintCT {
         /// Cluster contract address
@@ -28,23 +105,225 @@ pub enum ExecuteMsg {
         tokens: Vec<String>,
         cluster_token: String,
         user: String,
+        /// Minimum cluster tokens minted
+        min_tokens: Option<Uint128>,
+        /// Minimum amount of each asset provided to the cluster
+        min_assets: Option<Vec<Asset>>,
+        /// Balances held before the swaps, not counted as bought
+        balances_before: Vec<Asset>,
+        receipt: MintReceipt,
     },
     _SendToUser {
         /// Cluster contract address
+        cluster_address: String,
+        /// Cluster token address
         cluster_token: String,
         user: String,
+        receipt: MintReceipt,
+    },
+    _MintMany {
+        user: String,
+        /// Swaps of the UST summed over the clusters
+        swaps: Vec<SwapLeg>,
+        /// Balances held before the swaps, not counted as bought
+        balances_before: Vec<Asset>,
+        mints: Vec<ClusterMint>,
+    },
+    _SwitchSell {
+        /// Cluster contract address the assets were redeemed from
+        from_cluster: String,
+        /// Cluster contract address to mint
+        to_cluster: String,
+        user: String,
+        /// Minimum cluster tokens minted
+        min_out: Option<Uint128>,
+    },
+    _SwitchBuy {
+        /// Cluster contract address to mint
+        to_cluster: String,
+        user: String,
+        /// Minimum cluster tokens minted
+        min_out: Option<Uint128>,
+        /// UST value missing of each target asset, weighting the UST of the sales
+        deficits: Vec<Asset>,
+        /// UST value of the redeemed assets, counted against the mint caps
+        value: Uint128,
+    },
+}
+
+/// ## Description
+/// This enum describes the messages accepted along with cluster tokens sent to the contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    /// Redeems the cluster tokens sent, trades the redeemed basket into the target basket
+    /// of `to_cluster` and mints its cluster token to the sender
+    SwitchCluster {
+        /// Cluster contract address to mint
+        to_cluster: String,
+        /// Minimum cluster tokens minted
+        min_out: Option<Uint128>,
     },
+    /// Mints `cluster_address` with the target asset sent provided in kind, swapping no UST
+    MintCT {
+        /// Cluster contract address
+        cluster_address: String,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+        /// Integrator credited with the mint
+        referrer: Option<String>,
+        /// Share of the UST sent to the referrer in basis points
+        referral_bps: Option<u16>,
+        /// Quote of a prior `SimulateMint` the assets received must honour
+        quote: Option<MintQuote>,
+    },
+}
+
+/// ## Description
+/// One cluster of a `MintMany`, carried to `_MintMany` to hand it its share of the assets bought.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterMint {
+    pub cluster_address: String,
+    pub cluster_token: String,
+    /// UST spent on each target asset of the cluster
+    pub assets: Vec<Asset>,
+    /// Minimum cluster tokens minted
+    pub min_tokens: Option<Uint128>,
+    pub receipt: MintReceipt,
+}
+
+/// ## Description
+/// The asset amounts a `MintCT` must provide to the cluster, taken from a prior `SimulateMint`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintQuote {
+    /// `create_asset_amounts` of the simulation, in the order of the cluster target
+    pub create_asset_amounts: Vec<Uint128>,
+    /// Share of each quoted amount the mint may fall short by
+    pub tolerance: Decimal,
+}
+
+/// ## Description
+/// The amounts of a mint, carried through `_MintCT` and `_SendToUser` to report the mint
+/// once the cluster tokens are delivered.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MintReceipt {
+    /// UST paid by the user, without the refund
+    pub ust_in: Uint128,
+    /// UST sent to the fee collector
+    pub protocol_fee: Uint128,
+    /// UST sent to the referrer
+    pub referral_fee: Uint128,
+    /// UST sent back to the user
+    pub refund: Uint128,
+    /// UST spent on each target asset
+    pub swaps: Vec<SwapLeg>,
+}
+
+/// ## Description
+/// The UST spent on a target asset and the venue it is bought on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapLeg {
+    pub asset: AssetInfo,
+    /// `none`, `terra_market`, `astroport` or `anchor`
+    pub venue: String,
+    pub ust_in: Uint128,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
+    /// Returns the allowlisted clusters
+    Clusters {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Returns the UST that can still be minted into a cluster
+    MintCapacity { cluster_address: String },
+    /// Returns whether the contract is paused
+    Status {},
+    /// Returns the cumulative mints credited to a referrer
+    ReferralStats { address: String },
+    /// Returns the completed mints, newest first, of a user or into a cluster
+    MintHistory {
+        user: Option<String>,
+        cluster: Option<String>,
+        /// Id of the last mint of the previous page
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Returns the cumulative mints into a cluster
+    ClusterVolume { cluster_address: String },
     SimulateMint {
         cluster_address: String,
         ust_amount: Uint128,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+        /// Block height to compute the mint at, defaults to the current height
+        block_height: Option<u64>,
+        /// Maximum age of the oracle prices in seconds, fails on older prices
+        max_price_age: Option<u64>,
+        /// Share of the UST sent to a referrer in basis points
+        referral_bps: Option<u16>,
+        /// Target assets provided in kind besides the UST
+        in_kind: Option<Vec<Asset>>,
+    },
+    /// Simulates several mints at once, reporting failures per request
+    SimulateMintBatch {
+        /// Cluster contract addresses with the UST amount to simulate
+        requests: Vec<(String, Uint128)>,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+    },
+    /// Simulates a `MintMany` of `ust_amount`
+    SimulateMintMany {
+        /// Cluster contract addresses with their weight
+        allocations: Vec<(String, Uint128)>,
+        ust_amount: Uint128,
+        /// How to split the UST between the assets of each cluster, defaults to `Target`
+        allocation: Option<AllocationMode>,
+    },
+    /// Simulates switching `amount` cluster tokens of `from_cluster` into `to_cluster`
+    SimulateSwitchCluster {
+        from_cluster: String,
+        to_cluster: String,
+        amount: Uint128,
+    },
+    /// Returns the UST amount needed to mint `cluster_token_amount` cluster tokens
+    SimulateMintExact {
+        cluster_address: String,
+        cluster_token_amount: Uint128,
+        /// How to split the UST between the cluster assets, defaults to `Target`
+        allocation: Option<AllocationMode>,
+        /// Relative precision of the UST amount search, defaults to 0.1%
+        tolerance: Option<Decimal>,
     },
 }
 
+/// ## Description
+/// This enum describes how the provided UST is split between the assets of a cluster.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AllocationMode {
+    /// Buy every asset in its target proportion
+    Target,
+    /// Overweight the assets the cluster holds below target, so the mint moves
+    /// the inventory toward target and earns the rebalancing reward
+    Rebalance,
+    /// Evaluate blends between `Target` and `Rebalance` against the penalty
+    /// contract and use the one that mints the most cluster tokens
+    Optimize,
+}
+
+impl fmt::Display for AllocationMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocationMode::Target => write!(f, "target"),
+            AllocationMode::Rebalance => write!(f, "rebalance"),
+            AllocationMode::Optimize => write!(f, "optimize"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ClusterStateResponse {
     /// The current total supply of the cluster token
@@ -74,6 +353,180 @@ pub struct SimulateMintResponse {
     /// Returned attributes to the caller
     pub attributes: Vec<Attribute>,
     pub create_asset_amounts: Vec<Uint128>,
+    /// Share of the UST amount sent to the referrer
+    pub referral_fee: Uint128,
+    /// Protocol fee taken from the UST amount left after the referral share
+    pub protocol_fee: Uint128,
+    /// Breakdown of the purchase of each target asset
+    pub assets: Vec<SimulateMintAsset>,
+    /// Total spread and commission paid on the swaps, in UST
+    pub total_fees: Uint128,
+    /// UST paid per minted cluster token, in-kind assets counted at the cluster prices
+    pub effective_price: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClustersResponse {
+    pub clusters: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintCapacityResponse {
+    /// Maximum UST per mint, `None` when uncapped
+    pub max_ust_per_mint: Option<Uint128>,
+    /// Maximum UST minted into the cluster per window, `None` when uncapped
+    pub cluster_window_cap: Option<Uint128>,
+    /// Start of the rolling window in seconds
+    pub window_start: u64,
+    /// End of the rolling window in seconds, the current block time
+    pub window_end: u64,
+    /// UST minted into the cluster in the rolling window
+    pub window_used: Uint128,
+    /// Largest UST amount accepted by the next mint, `None` when uncapped
+    pub remaining: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StatusResponse {
+    pub paused: bool,
+    pub guardian: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralStatsResponse {
+    pub address: String,
+    /// Total UST sent to mints credited to the referrer
+    pub volume: Uint128,
+    /// Total UST paid to the referrer
+    pub fees: Uint128,
+    /// Number of mints credited to the referrer
+    pub mints: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintHistoryResponse {
+    pub mints: Vec<MintRecordResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintRecordResponse {
+    pub id: u64,
+    pub user: String,
+    pub cluster: String,
+    /// UST paid by the user, without the refund
+    pub ust_in: Uint128,
+    pub cluster_tokens_out: Uint128,
+    pub block_height: u64,
+    /// Block time in seconds
+    pub block_time: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterVolumeResponse {
+    pub cluster_address: String,
+    /// Total UST paid for mints into the cluster
+    pub ust_in: Uint128,
+    /// Total cluster tokens delivered
+    pub cluster_tokens_out: Uint128,
+    /// Number of mints into the cluster
+    pub mints: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintBatchResponse {
+    /// Results in the order of the requests
+    pub results: Vec<SimulateMintBatchResult>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintBatchResult {
+    pub cluster_address: String,
+    pub ust_amount: Uint128,
+    /// Simulation of the mint, `None` when it failed
+    pub simulation: Option<SimulateMintResponse>,
+    /// Reason the simulation failed
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintManyResponse {
+    /// Mints in the order of the allocations
+    pub mints: Vec<SimulateMintManyResult>,
+    /// Swaps of the UST summed over the clusters
+    pub assets: Vec<SimulateMintAsset>,
+    /// Total UST sent to the fee collector
+    pub protocol_fee: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintManyResult {
+    pub cluster_address: String,
+    /// UST of the deposit allocated to the cluster, fees included
+    pub ust_amount: Uint128,
+    pub create_tokens: Uint128,
+    pub penalty: Uint128,
+    /// Share of the bought assets provided to the cluster, aligned with its target
+    pub create_asset_amounts: Vec<Uint128>,
+    pub protocol_fee: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateSwitchClusterResponse {
+    /// Assets received from the pro rata redeem of `from_cluster`
+    pub redeem_assets: Vec<Asset>,
+    /// Amounts of the redeemed assets sold for UST, beyond the target of `to_cluster`
+    pub sold: Vec<Asset>,
+    /// UST received from the sales
+    pub ust_amount: Uint128,
+    /// UST of the sales sent to the fee collector
+    pub protocol_fee: Uint128,
+    /// Purchase of each target asset held below target
+    pub bought: Vec<SimulateMintAsset>,
+    pub create_tokens: Uint128,
+    pub penalty: Uint128,
+    /// Assets provided to `to_cluster`, aligned with its target
+    pub create_asset_amounts: Vec<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintExactResponse {
+    /// UST needed to mint the requested cluster token amount
+    pub ust_amount: Uint128,
+    /// Simulation of the mint with `ust_amount`
+    pub simulation: SimulateMintResponse,
+}
+
+/// ## Description
+/// A custom struct describing how a single target asset is bought during a mint.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulateMintAsset {
+    /// The target asset
+    pub info: AssetInfo,
+    /// UST allocated to the asset
+    pub ust_amount: Uint128,
+    /// Expected amount of the asset received
+    pub return_amount: Uint128,
+    /// Venue used to buy the asset
+    pub route: SwapRoute,
+    /// Spread of the swap, in the asset
+    pub spread_amount: Uint128,
+    /// Commission of the swap, in the asset
+    pub commission_amount: Uint128,
+}
+
+/// ## Description
+/// This enum describes the venue used to buy a target asset with UST.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapRoute {
+    /// The asset is UST itself, no swap needed
+    None {},
+    /// Swap through the Terra market module
+    TerraMarket {},
+    /// Swap through an Astroport pair
+    Astroport { pair_address: String },
+    /// Deposit into Anchor for aUST
+    Anchor {},
 }
 
 /// ## Description
@@ -81,8 +534,65 @@ pub struct SimulateMintResponse {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsgNebula {
+    /// Config returns contract settings specified in the custom [`ClusterConfig`] struct.
+    Config {},
     /// ClusterState returns the current cluster state.
     ClusterState {},
+    /// Target returns the current target assets of the cluster.
+    Target {},
+}
+
+/// ## Description
+/// A custom struct for each query response that returns the target assets of a cluster.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterTargetResponse {
+    /// Target assets with their weights
+    pub target: Vec<Asset>,
+}
+
+/// ## Description
+/// A custom struct for each query response that returns the cluster config.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterConfigResponse {
+    pub config: ClusterConfig,
+}
+
+/// ## Description
+/// A custom struct describing the settings of a cluster contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterConfig {
+    /// Name of the cluster
+    pub name: String,
+    /// Description of the cluster
+    pub description: String,
+    /// Owner of the cluster
+    pub owner: String,
+    /// Cluster token address
+    pub cluster_token: Option<String>,
+    /// Cluster factory that created the cluster
+    pub factory: String,
+    /// Pricing oracle address
+    pub pricing_oracle: String,
+    /// Target oracle address
+    pub target_oracle: String,
+    /// Penalty contract address
+    pub penalty: String,
+    /// The cluster active status - not active if decommissioned
+    pub active: bool,
+}
+
+/// ## Description
+/// This structure describes the available query messages for the cluster factory contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsgClusterFactory {
+    /// ClusterExists returns whether a cluster was created by the factory.
+    ClusterExists { contract_addr: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterExistsResponse {
+    pub exists: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -97,6 +607,15 @@ pub enum IncentivesMsg {
         /// minimum cluster tokens returned
         min_tokens: Option<Uint128>,
     },
+    /// IncentivesRedeem executes the redeem operation on a specific cluster.
+    IncentivesRedeem {
+        /// cluster contract
+        cluster_contract: String,
+        /// maximum amount of cluster tokens (CT) allowed to be burned
+        max_tokens: Uint128,
+        /// specific asset amounts returned from burning cluster tokens, pro rata when `None`
+        asset_amounts: Option<Vec<Asset>>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -117,6 +636,37 @@ pub enum QueryMsgPenalty {
         /// current target weights of the assets in a cluster
         target_weights: Vec<Uint128>,
     },
+    /// Params returns the penalty parameters and the current EMA of the penalty contract.
+    Params {},
+}
+
+/// ## Description
+/// A custom struct describing the penalty and reward curve of a penalty contract.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PenaltyParams {
+    /// Penalty applied below the low cutoff
+    pub penalty_amt_lo: Decimal,
+    /// Imbalance from which the low penalty applies
+    pub penalty_cutoff_lo: Decimal,
+    /// Penalty applied above the high cutoff
+    pub penalty_amt_hi: Decimal,
+    /// Imbalance from which the high penalty applies
+    pub penalty_cutoff_hi: Decimal,
+    /// Reward paid for rebalancing the cluster
+    pub reward_amt: Decimal,
+    /// Imbalance up to which the reward applies
+    pub reward_cutoff: Decimal,
+}
+
+/// ## Description
+/// A custom struct for each query response that returns the penalty parameters.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PenaltyParamsResponse {
+    pub penalty_params: PenaltyParams,
+    /// Exponential moving average of the cluster net asset value
+    pub ema: Decimal,
+    /// Block height of the last EMA update
+    pub last_block: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -125,6 +675,12 @@ pub enum AnchorMsg {
     DepositStable {},
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorCw20HookMsg {
+    RedeemStable {},
+}
+
 /// ## Description
 /// This structure describes the available query messages for the oracle hub contract.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -146,7 +702,7 @@ pub struct PriceResponse {
 
 /// ## Description
 /// A custom struct for each query that returns the actual mint amount and the subjected penalty.
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct PenaltyCreateResponse {
     /// Actual minted cluster token amount
     pub create_tokens: Uint128,
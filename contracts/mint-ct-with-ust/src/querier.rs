@@ -0,0 +1,150 @@
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, QuerierWrapper, StdResult, Uint128};
+
+use crate::msg::{
+    ClusterConfig, ClusterConfigResponse, ClusterExistsResponse, ClusterStateResponse,
+    ClusterTargetResponse, PenaltyCreateResponse, PenaltyParamsResponse, PriceResponse,
+    QueryMsgClusterFactory, QueryMsgNebula, QueryMsgOracleHub, QueryMsgPenalty,
+};
+use astroport::asset::Asset;
+
+/// ## Description
+/// A wrapper around the address of a Nebula cluster contract running its queries.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterContract(pub Addr);
+
+impl ClusterContract {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    fn query<T: DeserializeOwned>(
+        &self,
+        querier: &QuerierWrapper,
+        msg: &QueryMsgNebula,
+    ) -> StdResult<T> {
+        querier.query_wasm_smart(self.addr(), msg)
+    }
+
+    /// ## Description
+    /// Returns the config of the cluster.
+    pub fn config(&self, querier: &QuerierWrapper) -> StdResult<ClusterConfig> {
+        let response: ClusterConfigResponse = self.query(querier, &QueryMsgNebula::Config {})?;
+
+        Ok(response.config)
+    }
+
+    /// ## Description
+    /// Returns the state of the cluster: inventory, prices, supply and target.
+    pub fn state(&self, querier: &QuerierWrapper) -> StdResult<ClusterStateResponse> {
+        self.query(querier, &QueryMsgNebula::ClusterState {})
+    }
+
+    /// ## Description
+    /// Returns the target assets of the cluster with their weights.
+    pub fn target(&self, querier: &QuerierWrapper) -> StdResult<Vec<Asset>> {
+        let response: ClusterTargetResponse = self.query(querier, &QueryMsgNebula::Target {})?;
+
+        Ok(response.target)
+    }
+}
+
+/// ## Description
+/// A wrapper around the address of a Nebula cluster factory running its queries.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterFactory(pub Addr);
+
+impl ClusterFactory {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /// ## Description
+    /// Returns whether `cluster` was created by the factory.
+    pub fn cluster_exists(&self, querier: &QuerierWrapper, cluster: &Addr) -> StdResult<bool> {
+        let response: ClusterExistsResponse = querier.query_wasm_smart(
+            self.addr(),
+            &QueryMsgClusterFactory::ClusterExists {
+                contract_addr: cluster.to_string(),
+            },
+        )?;
+
+        Ok(response.exists)
+    }
+}
+
+/// ## Description
+/// A wrapper around the address of the penalty contract of a cluster running its queries.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PenaltyContract(pub Addr);
+
+impl PenaltyContract {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /// ## Description
+    /// Returns the cluster tokens minted at `block_height` by providing `create_asset_amounts`
+    /// to the cluster described by `cluster_state`, with the penalty or reward applied.
+    pub fn create(
+        &self,
+        querier: &QuerierWrapper,
+        block_height: u64,
+        cluster_state: &ClusterStateResponse,
+        create_asset_amounts: Vec<Uint128>,
+    ) -> StdResult<PenaltyCreateResponse> {
+        querier.query_wasm_smart(
+            self.addr(),
+            &QueryMsgPenalty::PenaltyQueryCreate {
+                block_height,
+                cluster_token_supply: cluster_state.outstanding_balance_tokens,
+                inventory: cluster_state.inv.clone(),
+                create_asset_amounts,
+                asset_prices: cluster_state.prices.clone(),
+                target_weights: cluster_state
+                    .target
+                    .iter()
+                    .map(|asset| asset.amount)
+                    .collect(),
+            },
+        )
+    }
+
+    /// ## Description
+    /// Returns the penalty parameters and the current EMA of the penalty contract.
+    pub fn params(&self, querier: &QuerierWrapper) -> StdResult<PenaltyParamsResponse> {
+        querier.query_wasm_smart(self.addr(), &QueryMsgPenalty::Params {})
+    }
+}
+
+/// ## Description
+/// A wrapper around the address of the oracle hub running its queries.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OracleHub(pub Addr);
+
+impl OracleHub {
+    pub fn addr(&self) -> Addr {
+        self.0.clone()
+    }
+
+    /// ## Description
+    /// Returns the UST price of `asset_token`, failing when no price was published
+    /// within `timeframe` seconds. Without a timeframe the age of the price is ignored.
+    pub fn price(
+        &self,
+        querier: &QuerierWrapper,
+        asset_token: impl Into<String>,
+        timeframe: Option<u64>,
+    ) -> StdResult<PriceResponse> {
+        querier.query_wasm_smart(
+            self.addr(),
+            &QueryMsgOracleHub::Price {
+                asset_token: asset_token.into(),
+                timeframe,
+            },
+        )
+    }
+}
@@ -1,11 +1,11 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::Addr;
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex, U64Key};
 
 //////////////////////////////////////////////////////////////////////
-/// STATE
+// STATE
 //////////////////////////////////////////////////////////////////////
 
 /// ## Description
@@ -18,6 +18,118 @@ pub struct State {
     pub anchor_market_contract: Addr,
     pub oracle_hub_contract: Addr,
     pub owner_address: Addr,
+    /// Receiver of the protocol fee
+    pub fee_collector: Addr,
+    /// Protocol fee taken from the UST of each mint, in basis points
+    pub protocol_fee_bps: u16,
+    /// Maximum share of a mint a referrer can receive, in basis points
+    pub max_referral_bps: u16,
+    /// Address allowed to pause the contract besides the owner
+    pub guardian: Addr,
+    /// Whether execute messages are rejected
+    pub paused: bool,
+    /// Nebula cluster factory used to verify cluster tokens
+    pub cluster_factory: Option<Addr>,
+    /// Maximum UST per mint
+    pub max_ust_per_mint: Option<Uint128>,
+    /// Maximum UST minted per cluster in any rolling window
+    pub cluster_window_cap: Option<Uint128>,
+    /// Length of the per-cluster cap window in seconds
+    pub cap_window_seconds: u64,
+    /// Maximum deviation of Astroport fills from the oracle hub price
+    pub max_price_deviation: Option<Decimal>,
 }
 
 pub const STATE: Item<State> = Item::new("state");
+
+/// ## Description
+/// A custom struct for storing the cumulative mints credited to a referrer.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ReferralStats {
+    pub volume: Uint128,
+    pub fees: Uint128,
+    pub mints: u64,
+}
+
+/// Clusters allowed to be minted
+pub const ALLOWED_CLUSTERS: Map<&Addr, bool> = Map::new("allowed_clusters");
+
+/// Allowed cluster of each cluster token, kept along with `ALLOWED_CLUSTERS`
+pub const CLUSTER_TOKENS: Map<&Addr, Addr> = Map::new("cluster_tokens");
+
+/// ## Description
+/// A custom struct for storing the UST minted into a cluster during a bucket of its cap
+/// window. The bucket keeps its own length, so changing the window does not resize it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MintBucket {
+    /// Length of the bucket in seconds
+    pub seconds: u64,
+    pub amount: Uint128,
+}
+
+/// UST minted per cluster, keyed by cluster and start of the bucket of the cap window
+pub const MINT_WINDOWS: Map<(&Addr, U64Key), MintBucket> = Map::new("mint_windows");
+
+pub const REFERRAL_STATS: Map<&Addr, ReferralStats> = Map::new("referral_stats");
+
+//////////////////////////////////////////////////////////////////////
+// MINT HISTORY
+//////////////////////////////////////////////////////////////////////
+
+/// ## Description
+/// A custom struct for storing a completed mint.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MintRecord {
+    pub user: Addr,
+    pub cluster: Addr,
+    /// UST paid by the user, without the refund
+    pub ust_in: Uint128,
+    pub cluster_tokens_out: Uint128,
+    pub block_height: u64,
+    /// Block time in seconds
+    pub block_time: u64,
+}
+
+pub struct MintRecordIndexes<'a> {
+    pub user: MultiIndex<'a, (Vec<u8>, Vec<u8>), MintRecord>,
+    pub cluster: MultiIndex<'a, (Vec<u8>, Vec<u8>), MintRecord>,
+}
+
+impl<'a> IndexList<MintRecord> for MintRecordIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<MintRecord>> + '_> {
+        let v: Vec<&dyn Index<MintRecord>> = vec![&self.user, &self.cluster];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Completed mints keyed by an increasing id, indexed by user and by cluster
+pub fn mint_history<'a>() -> IndexedMap<'a, U64Key, MintRecord, MintRecordIndexes<'a>> {
+    let indexes = MintRecordIndexes {
+        user: MultiIndex::new(
+            |record: &MintRecord, pk: Vec<u8>| (record.user.as_bytes().to_vec(), pk),
+            "mint_history",
+            "mint_history__user",
+        ),
+        cluster: MultiIndex::new(
+            |record: &MintRecord, pk: Vec<u8>| (record.cluster.as_bytes().to_vec(), pk),
+            "mint_history",
+            "mint_history__cluster",
+        ),
+    };
+
+    IndexedMap::new("mint_history", indexes)
+}
+
+/// Id of the next mint record
+pub const MINT_COUNT: Item<u64> = Item::new("mint_count");
+
+/// ## Description
+/// A custom struct for storing the cumulative mints into a cluster.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct ClusterVolume {
+    pub ust_in: Uint128,
+    pub cluster_tokens_out: Uint128,
+    pub mints: u64,
+}
+
+pub const CLUSTER_VOLUMES: Map<&Addr, ClusterVolume> = Map::new("cluster_volumes");
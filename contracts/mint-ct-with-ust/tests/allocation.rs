@@ -0,0 +1,254 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use astroport::asset::{Asset, AssetInfo};
+use mint_ct_with_ust::contract::{
+    aggregate_assets, in_kind_allocation, normalize_cluster_state, plan_switch,
+    rebalance_allocation, split_assets, split_by_weights, target_allocation,
+};
+use mint_ct_with_ust::msg::ClusterStateResponse;
+
+fn target(weights: &[u128]) -> Vec<Asset> {
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, weight)| Asset {
+            info: AssetInfo::Token {
+                contract_addr: Addr::unchecked(format!("asset{}", i)),
+            },
+            amount: Uint128::new(*weight),
+        })
+        .collect()
+}
+
+fn asset(index: usize, amount: u128) -> Asset {
+    Asset {
+        info: AssetInfo::Token {
+            contract_addr: Addr::unchecked(format!("asset{}", index)),
+        },
+        amount: Uint128::new(amount),
+    }
+}
+
+fn cluster_state(weights: &[u128], inv: &[u128], prices: &[u64]) -> ClusterStateResponse {
+    ClusterStateResponse {
+        outstanding_balance_tokens: Uint128::new(1_000_000),
+        prices: prices
+            .iter()
+            .map(|price| Decimal::permille(*price).to_string())
+            .collect(),
+        inv: inv.iter().map(|amount| Uint128::new(*amount)).collect(),
+        penalty: "penalty".to_string(),
+        cluster_token: "cluster_token".to_string(),
+        target: target(weights),
+        cluster_contract_address: "cluster".to_string(),
+        active: true,
+    }
+}
+
+/// Asserts that `shares` split `amount` without exceeding it and that less dust is left
+/// than there are shares.
+fn assert_split(amount: u128, shares: &[Uint128], len: usize) {
+    assert_eq!(shares.len(), len);
+    let total: u128 = shares.iter().map(|share| share.u128()).sum();
+    assert!(total <= amount);
+    assert!(amount - total < len as u128);
+}
+
+proptest! {
+    #[test]
+    fn split_by_weights_never_exceeds_amount(
+        amount in any::<u128>(),
+        weights in vec(0u128..=u64::MAX as u128, 1..10),
+    ) {
+        let weights: Vec<Uint128> = weights.into_iter().map(Uint128::new).collect();
+        match split_by_weights(Uint128::new(amount), &weights) {
+            Ok(shares) => {
+                assert_split(amount, &shares, weights.len());
+                for (share, weight) in shares.iter().zip(weights.iter()) {
+                    if weight.is_zero() {
+                        prop_assert!(share.is_zero());
+                    }
+                }
+            }
+            Err(_) => prop_assert!(weights.iter().all(|weight| weight.is_zero())),
+        }
+    }
+
+    #[test]
+    fn split_by_weights_rejects_overflowing_weights(amount in any::<u128>(), len in 2usize..5) {
+        let weights = vec![Uint128::MAX; len];
+        prop_assert!(split_by_weights(Uint128::new(amount), &weights).is_err());
+    }
+
+    #[test]
+    fn split_by_weights_gives_single_asset_everything(
+        amount in any::<u128>(),
+        weight in 1u128..,
+    ) {
+        let shares = split_by_weights(Uint128::new(amount), &[Uint128::new(weight)]).unwrap();
+        prop_assert_eq!(shares, vec![Uint128::new(amount)]);
+    }
+
+    #[test]
+    fn target_allocation_follows_weights(
+        amount in 0u128..=u64::MAX as u128,
+        weights in vec(0u128..1_000, 1..10),
+    ) {
+        let total_weight: u128 = weights.iter().sum();
+        match target_allocation(Uint128::new(amount), &target(&weights)) {
+            Ok(allocation) => {
+                assert_split(amount, &allocation, weights.len());
+                for (share, weight) in allocation.iter().zip(weights.iter()) {
+                    // never below the rounded down share
+                    prop_assert!(share.u128() >= amount * weight / total_weight);
+                }
+            }
+            Err(_) => prop_assert_eq!(total_weight, 0),
+        }
+    }
+
+    #[test]
+    fn rebalance_allocation_never_exceeds_amount(
+        amount in 0u128..=u64::MAX as u128,
+        assets in vec((0u128..1_000, 0u128..=u64::MAX as u128, 1u64..1_000_000), 1..10),
+    ) {
+        let weights: Vec<u128> = assets.iter().map(|(weight, _, _)| *weight).collect();
+        let inv: Vec<u128> = assets.iter().map(|(_, inv, _)| *inv).collect();
+        let prices: Vec<u64> = assets.iter().map(|(_, _, price)| *price).collect();
+
+        match rebalance_allocation(Uint128::new(amount), &cluster_state(&weights, &inv, &prices)) {
+            Ok(allocation) => {
+                assert_split(amount, &allocation, weights.len());
+                for (share, weight) in allocation.iter().zip(weights.iter()) {
+                    if *weight == 0 {
+                        prop_assert!(share.is_zero());
+                    }
+                }
+            }
+            Err(_) => prop_assert!(weights.iter().all(|weight| *weight == 0)),
+        }
+    }
+
+    #[test]
+    fn split_assets_hands_out_every_asset_bought(
+        baskets in vec(vec((0usize..4, 0u128..=u64::MAX as u128), 1..6), 1..5),
+        bought in vec(0u128..=u64::MAX as u128, 4),
+    ) {
+        let baskets: Vec<Vec<Asset>> = baskets
+            .iter()
+            .map(|basket| basket.iter().map(|(index, amount)| asset(*index, *amount)).collect())
+            .collect();
+        let bought: Vec<Asset> = bought
+            .iter()
+            .enumerate()
+            .map(|(index, amount)| asset(index, *amount))
+            .collect();
+
+        let shares = split_assets(&baskets, &bought).unwrap();
+        prop_assert_eq!(shares.len(), baskets.len());
+        let totals = aggregate_assets(&baskets).unwrap();
+        for bought in bought.iter() {
+            let handed_out: u128 = shares
+                .iter()
+                .flatten()
+                .filter(|share| share.info == bought.info)
+                .map(|share| share.amount.u128())
+                .sum();
+            match totals.iter().find(|total| total.info == bought.info) {
+                Some(total) if !total.amount.is_zero() => prop_assert_eq!(handed_out, bought.amount.u128()),
+                _ => prop_assert_eq!(handed_out, 0),
+            }
+        }
+    }
+}
+
+#[test]
+fn split_assets_follows_the_ust_spent() {
+    // both baskets hold asset0, only the first one holds asset1
+    let baskets = vec![
+        vec![asset(0, 300), asset(1, 100)],
+        vec![asset(0, 100), asset(0, 100)],
+    ];
+    let shares = split_assets(&baskets, &[asset(0, 1_000), asset(1, 50)]).unwrap();
+    assert_eq!(shares[0], vec![asset(0, 600), asset(1, 50)]);
+    assert_eq!(shares[1], vec![asset(0, 400)]);
+}
+
+#[test]
+fn target_allocation_rejects_zero_weights() {
+    assert!(target_allocation(Uint128::new(1_000), &target(&[0, 0])).is_err());
+    assert!(target_allocation(Uint128::new(1_000), &[]).is_err());
+}
+
+#[test]
+fn target_allocation_keeps_no_dust() {
+    let allocation = target_allocation(Uint128::new(100), &target(&[1, 1, 1])).unwrap();
+    assert_eq!(
+        allocation,
+        vec![Uint128::new(33), Uint128::new(33), Uint128::new(34)]
+    );
+}
+
+#[test]
+fn rebalance_allocation_fills_deficits_first() {
+    // the second asset holds nothing, so the whole mint goes to it
+    let state = cluster_state(&[50, 50], &[1_000_000, 0], &[1_000, 1_000]);
+    let allocation = rebalance_allocation(Uint128::new(500_000), &state).unwrap();
+    assert_eq!(allocation, vec![Uint128::zero(), Uint128::new(500_000)]);
+}
+
+#[test]
+fn normalize_cluster_state_merges_duplicate_assets() {
+    let mut state = cluster_state(&[20, 50, 30], &[100, 200, 300], &[1_000, 2_000, 3_000]);
+    state.target[2].info = state.target[0].info.clone();
+
+    let state = normalize_cluster_state(state).unwrap();
+    assert_eq!(state.target, vec![asset(0, 50), asset(1, 50)]);
+    assert_eq!(state.inv, vec![Uint128::new(400), Uint128::new(200)]);
+    assert_eq!(state.prices.len(), 2);
+    assert_eq!(Decimal::from_str(&state.prices[0]).unwrap(), Decimal::one());
+
+    // a single entry per asset is left untouched
+    let distinct = cluster_state(&[20, 80], &[100, 200], &[1_000, 2_000]);
+    assert_eq!(normalize_cluster_state(distinct.clone()).unwrap(), distinct);
+}
+
+#[test]
+fn plan_switch_trades_only_the_difference() {
+    let from = cluster_state(&[50, 50], &[0, 0], &[1_000, 1_000]);
+    // the new cluster drops asset0 for asset2 and prices asset1 at 2 UST
+    let mut to = cluster_state(&[50, 50], &[0, 0], &[1_000, 2_000]);
+    to.target[0].info = asset(2, 0).info;
+
+    let plan = plan_switch(&[asset(0, 1_000), asset(1, 1_000)], &from, &to).unwrap();
+    // 2000 UST split 50/50 is 1000 asset2 and 500 asset1
+    assert_eq!(plan.sells, vec![asset(0, 1_000), asset(1, 500)]);
+    assert_eq!(plan.deficits, vec![asset(2, 1_000), asset(1, 0)]);
+    assert_eq!(plan.value, Uint128::new(2_000));
+
+    // a basket already on target trades nothing
+    let plan = plan_switch(&[asset(0, 1_000), asset(1, 1_000)], &from, &from).unwrap();
+    assert!(plan.sells.is_empty());
+    assert_eq!(plan.deficits, vec![asset(0, 0), asset(1, 0)]);
+}
+
+#[test]
+fn in_kind_allocation_fills_the_shortfall() {
+    let state = cluster_state(&[50, 50], &[0, 0], &[1_000, 2_000]);
+    // 1000 UST of asset0 in kind leaves the whole 1000 UST to asset1
+    let allocation = in_kind_allocation(Uint128::new(1_000), &state, &[asset(0, 1_000)]).unwrap();
+    assert_eq!(allocation, vec![Uint128::zero(), Uint128::new(1_000)]);
+
+    // in-kind assets on target leave the UST to the target weights
+    let allocation = in_kind_allocation(
+        Uint128::new(1_000),
+        &state,
+        &[asset(0, 1_000), asset(1, 500)],
+    )
+    .unwrap();
+    assert_eq!(allocation, vec![Uint128::new(500), Uint128::new(500)]);
+}
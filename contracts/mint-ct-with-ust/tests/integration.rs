@@ -0,0 +1,1492 @@
+mod suite;
+
+use cosmwasm_std::{coin, coins, Addr, Decimal, Event, StdResult, Uint128};
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+
+use astroport::asset::Asset;
+use mint_ct_with_ust::helpers::MintOptions;
+use mint_ct_with_ust::msg::{
+    AllocationMode, ExecuteMsg, MintQuote, MintReceipt, QueryMsg, SimulateMintExactResponse,
+    SimulateMintResponse, SwapRoute,
+};
+use mint_ct_with_ust::querier::{ClusterContract, OracleHub, PenaltyContract};
+use mint_ct_with_ust::ContractError;
+
+use suite::{aust_rate, Suite, KRW_PER_UST, MARKET, OWNER, TOKEN_PRICE, USER, UST_PER_EUR};
+
+#[test]
+fn mint_ct_matches_simulation() {
+    let mut suite = Suite::new();
+    let simulation = suite.simulate_mint(100_000_000);
+    assert!(!simulation.create_tokens.is_zero());
+    assert_eq!(
+        simulation.effective_price,
+        Decimal::from_ratio(100_000_000u128, simulation.create_tokens)
+    );
+    // the market keeps 0.5% of the 20 UST of ukrw and Astroport 0.3% of the 50 UST of the
+    // token, less the rounding of the few token units bought, while Anchor is free
+    assert!(simulation.total_fees <= Uint128::new(250_000));
+    assert!(simulation.total_fees >= Uint128::new(249_500));
+
+    suite.mint(100_000_000).unwrap();
+
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        simulation.create_tokens
+    );
+    // every bought asset went into the cluster
+    let mint_utils = suite.mint_utils.to_string();
+    assert_eq!(
+        suite.token_balance(&suite.token, &mint_utils),
+        Uint128::zero()
+    );
+    assert_eq!(
+        suite.token_balance(&suite.aust_token, &mint_utils),
+        Uint128::zero()
+    );
+    assert_eq!(
+        suite
+            .app
+            .wrap()
+            .query_balance(&mint_utils, "ukrw")
+            .unwrap()
+            .amount,
+        Uint128::zero()
+    );
+}
+
+fn attribute<'a>(event: &'a Event, key: &str) -> &'a str {
+    event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .map(|attr| attr.value.as_str())
+        .unwrap_or_else(|| panic!("{} has no {} attribute", event.ty, key))
+}
+
+#[test]
+fn mint_ct_emits_structured_events() {
+    let mut suite = Suite::new();
+    // tokens left in the contract are not reported as bought
+    suite
+        .app
+        .execute_contract(
+            suite.pair.clone(),
+            suite.token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: suite.mint_utils.to_string(),
+                amount: Uint128::new(1_000),
+            },
+            &[],
+        )
+        .unwrap();
+    let simulation = suite.simulate_mint(100_000_000);
+    let res = suite.mint(100_000_000).unwrap();
+    let events = |ty: &str| -> Vec<&Event> { res.events.iter().filter(|e| e.ty == ty).collect() };
+
+    let mint = events("wasm-mint_ct");
+    assert_eq!(mint.len(), 1);
+    assert_eq!(attribute(mint[0], "action"), "mint_ct");
+    assert_eq!(attribute(mint[0], "user"), USER);
+    assert_eq!(attribute(mint[0], "cluster"), suite.cluster.as_str());
+    assert_eq!(attribute(mint[0], "ust_in"), "100000000");
+
+    let swaps = events("wasm-swap");
+    let venues: Vec<&str> = swaps.iter().map(|e| attribute(e, "venue")).collect();
+    assert_eq!(venues, vec!["terra_market", "astroport", "anchor"]);
+    for (swap, asset) in swaps.iter().zip(simulation.assets.iter()) {
+        assert_eq!(attribute(swap, "ust_in"), asset.ust_amount.to_string());
+        assert_eq!(
+            attribute(swap, "amount_out"),
+            asset.return_amount.to_string()
+        );
+    }
+
+    let complete = events("wasm-mint_complete");
+    assert_eq!(complete.len(), 1);
+    assert_eq!(
+        attribute(complete[0], "cluster_tokens_out"),
+        suite.token_balance(&suite.cluster_token, USER).to_string()
+    );
+    assert_eq!(attribute(complete[0], "refund"), "0");
+}
+
+#[test]
+fn mint_ct_msg_from_helper() {
+    let mut suite = Suite::new();
+    let helper = suite.helper();
+    let opts = MintOptions {
+        allocation: Some(AllocationMode::Rebalance),
+        ..MintOptions::default()
+    };
+    let simulation = helper
+        .simulate_mint_with(
+            &suite.app.wrap(),
+            &suite.cluster,
+            Uint128::new(100_000_000),
+            opts.clone(),
+        )
+        .unwrap();
+
+    let msg = helper
+        .mint_ct_msg(&suite.cluster, coins(100_000_000, "uusd"), opts)
+        .unwrap();
+    suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        simulation.create_tokens
+    );
+}
+
+#[test]
+fn mint_ct_with_every_allocation() {
+    for allocation in [
+        AllocationMode::Target,
+        AllocationMode::Rebalance,
+        AllocationMode::Optimize,
+    ] {
+        let mut suite = Suite::new();
+        let msg = ExecuteMsg::MintCT {
+            cluster_address: suite.cluster.to_string(),
+            allocation: Some(allocation),
+            referrer: None,
+            referral_bps: None,
+            quote: None,
+        };
+
+        suite.execute(USER, &msg, 100_000_000).unwrap();
+        assert!(!suite.token_balance(&suite.cluster_token, USER).is_zero());
+    }
+}
+
+#[test]
+fn mint_ct_rejects_removed_cluster() {
+    let mut suite = Suite::new();
+    let cluster_address = suite.cluster.to_string();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::RemoveCluster {
+                cluster_address: cluster_address.clone(),
+            },
+            0,
+        )
+        .unwrap();
+
+    let err = suite.mint(100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ClusterNotAllowed { cluster }) if *cluster == cluster_address
+    ));
+}
+
+#[test]
+fn mint_ct_rejected_while_paused() {
+    let mut suite = Suite::new();
+    let helper = suite.helper();
+    let status = helper.status(&suite.app.wrap()).unwrap();
+    assert!(!status.paused);
+    assert_eq!(status.guardian, OWNER);
+
+    suite.execute(OWNER, &ExecuteMsg::Pause {}, 0).unwrap();
+    assert!(helper.status(&suite.app.wrap()).unwrap().paused);
+
+    let err = suite.mint(100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::Paused {})
+    ));
+
+    suite.execute(OWNER, &ExecuteMsg::Unpause {}, 0).unwrap();
+    assert!(!helper.status(&suite.app.wrap()).unwrap().paused);
+    suite.mint(100_000_000).unwrap();
+}
+
+#[test]
+fn mint_ct_pays_protocol_and_referral_fees() {
+    let mut suite = Suite::new();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: Some(30),
+                fee_collector: Some("collector".to_string()),
+                max_referral_bps: Some(50),
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: None,
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: None,
+            },
+            0,
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::MintCT {
+        cluster_address: suite.cluster.to_string(),
+        allocation: None,
+        referrer: Some("referrer".to_string()),
+        referral_bps: Some(50),
+        quote: None,
+    };
+    suite.execute(USER, &msg, 100_000_000).unwrap();
+
+    // 0.5% of the mint goes to the referrer, then 0.3% of the rest to the collector
+    assert_eq!(suite.ust_balance("referrer"), Uint128::new(500_000));
+    assert_eq!(suite.ust_balance("collector"), Uint128::new(298_500));
+
+    suite.execute(USER, &msg, 50_000_000).unwrap();
+    let stats = suite
+        .helper()
+        .referral_stats(&suite.app.wrap(), "referrer")
+        .unwrap();
+    assert_eq!(stats.volume, Uint128::new(150_000_000));
+    assert_eq!(stats.fees, Uint128::new(750_000));
+    assert_eq!(stats.mints, 2);
+    assert_eq!(suite.ust_balance("referrer"), stats.fees);
+}
+
+#[test]
+fn mint_ct_respects_the_cluster_window_cap() {
+    let mut suite = Suite::new();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: None,
+                fee_collector: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: Some(Uint128::new(120_000_000)),
+                cluster_window_cap: Some(Uint128::new(150_000_000)),
+                cap_window_seconds: Some(2_400),
+                max_price_deviation: None,
+            },
+            0,
+        )
+        .unwrap();
+    let helper = suite.helper();
+    let capacity = |suite: &Suite| {
+        helper
+            .mint_capacity(&suite.app.wrap(), &suite.cluster)
+            .unwrap()
+    };
+
+    let start = capacity(&suite);
+    assert_eq!(start.window_used, Uint128::zero());
+    assert_eq!(start.window_end - start.window_start, 2_400);
+    // the next mint is held to the lower of both caps
+    assert_eq!(start.remaining, Some(Uint128::new(120_000_000)));
+
+    suite.mint(100_000_000).unwrap();
+    let filled = capacity(&suite);
+    assert_eq!(filled.window_used, Uint128::new(100_000_000));
+    assert_eq!(filled.remaining, Some(Uint128::new(50_000_000)));
+
+    let err = suite.mint(60_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::WindowCapExceeded { remaining, .. })
+            if *remaining == Uint128::new(50_000_000)
+    ));
+    suite.mint(50_000_000).unwrap();
+    assert_eq!(capacity(&suite).remaining, Some(Uint128::zero()));
+
+    // the window rolls past the mints once their 100 second bucket has left it
+    suite
+        .app
+        .update_block(|block| block.time = block.time.plus_seconds(2_500));
+    let rolled = capacity(&suite);
+    assert_eq!(rolled.window_used, Uint128::zero());
+    assert_eq!(rolled.remaining, Some(Uint128::new(120_000_000)));
+
+    suite.mint(100_000_000).unwrap();
+    assert_eq!(capacity(&suite).remaining, Some(Uint128::new(50_000_000)));
+}
+
+#[test]
+fn mint_ct_exact_refunds_surplus() {
+    let mut suite = Suite::new();
+    let cluster_token_amount = Uint128::new(50_000_000);
+    let quote: SimulateMintExactResponse = suite
+        .app
+        .wrap()
+        .query_wasm_smart(
+            suite.mint_utils.to_string(),
+            &QueryMsg::SimulateMintExact {
+                cluster_address: suite.cluster.to_string(),
+                cluster_token_amount,
+                allocation: None,
+                tolerance: None,
+            },
+        )
+        .unwrap();
+
+    let balance = suite.ust_balance(USER);
+    let msg = ExecuteMsg::MintCTExact {
+        cluster_address: suite.cluster.to_string(),
+        cluster_token_amount,
+        allocation: None,
+        tolerance: None,
+    };
+    suite.execute(USER, &msg, 100_000_000).unwrap();
+
+    assert!(suite.token_balance(&suite.cluster_token, USER) >= cluster_token_amount);
+    assert_eq!(suite.ust_balance(USER), balance - quote.ust_amount);
+}
+
+#[test]
+fn mint_ct_aborts_on_price_deviation() {
+    let mut suite = Suite::new();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: None,
+                fee_collector: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: None,
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: Some(Decimal::percent(2)),
+            },
+            0,
+        )
+        .unwrap();
+    suite.mint(100_000_000).unwrap();
+
+    // the pool now sells the token 10% above the oracle price
+    suite
+        .app
+        .execute_contract(
+            suite.pair.clone(),
+            suite.pair.clone(),
+            &suite::astroport::PairExecuteMsg::SetPrice {
+                price: Decimal::from_ratio(165u128, 1u128),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let err = suite.mint(100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::PriceDeviation { .. })
+    ));
+}
+
+#[test]
+fn mint_ct_aborts_on_a_zero_oracle_price() {
+    let mut suite = Suite::new();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: None,
+                fee_collector: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: None,
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: Some(Decimal::percent(2)),
+            },
+            0,
+        )
+        .unwrap();
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked(OWNER),
+            suite.oracle_hub.clone(),
+            &suite::oracle::ExecuteMsg::SetPrice {
+                asset_token: suite.token.to_string(),
+                rate: Decimal::zero(),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let err = suite.mint(100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ZeroOraclePrice { .. })
+    ));
+}
+
+#[test]
+fn mint_ct_honours_the_quote_of_a_simulation() {
+    let mut suite = Suite::new();
+    let simulation = suite.simulate_mint(100_000_000);
+    let opts = MintOptions {
+        quote: Some(MintQuote {
+            create_asset_amounts: simulation.create_asset_amounts.clone(),
+            tolerance: Decimal::percent(1),
+        }),
+        ..MintOptions::default()
+    };
+    let helper = suite.helper();
+    let msg = helper
+        .mint_ct_msg(&suite.cluster, coins(100_000_000, "uusd"), opts.clone())
+        .unwrap();
+    suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+    let minted = suite.token_balance(&suite.cluster_token, USER);
+    assert_eq!(minted, simulation.create_tokens);
+
+    // the pool now sells the token 10% above the quoted price
+    suite
+        .app
+        .execute_contract(
+            suite.pair.clone(),
+            suite.pair.clone(),
+            &suite::astroport::PairExecuteMsg::SetPrice {
+                price: Decimal::from_ratio(165u128, 1u128),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let msg = helper
+        .mint_ct_msg(&suite.cluster, coins(100_000_000, "uusd"), opts)
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err
+        .chain()
+        .any(|cause| cause.to_string().contains("below the quoted minimum")));
+    assert_eq!(suite.token_balance(&suite.cluster_token, USER), minted);
+}
+
+#[test]
+fn mint_ct_quote_counts_only_the_assets_of_the_mint() {
+    let mut suite = Suite::new();
+    let simulation = suite.simulate_mint(100_000_000);
+    let target = ClusterContract(suite.cluster.clone())
+        .state(&suite.app.wrap())
+        .unwrap()
+        .target;
+    let index = target
+        .iter()
+        .position(|asset| asset.info == suite.ukrw())
+        .unwrap();
+    // the quote asks for 10% more ukrw than the mint buys
+    let mut create_asset_amounts = simulation.create_asset_amounts.clone();
+    let extra = create_asset_amounts[index].multiply_ratio(1u128, 10u128);
+    create_asset_amounts[index] += extra;
+    let opts = MintOptions {
+        quote: Some(MintQuote {
+            create_asset_amounts,
+            tolerance: Decimal::zero(),
+        }),
+        ..MintOptions::default()
+    };
+
+    // ukrw left in the contract does not make up for the shortfall
+    suite
+        .app
+        .send_tokens(
+            Addr::unchecked(USER),
+            suite.mint_utils.clone(),
+            &coins(extra.u128(), "ukrw"),
+        )
+        .unwrap();
+    let msg = suite
+        .helper()
+        .mint_ct_msg(&suite.cluster, coins(100_000_000, "uusd"), opts)
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err
+        .chain()
+        .any(|cause| cause.to_string().contains("below the quoted minimum")));
+}
+
+#[test]
+fn mint_ct_rejects_unregistered_cluster_token() {
+    let mut suite = Suite::new();
+    // a factory that never created the cluster
+    let code_id = suite
+        .app
+        .store_code(suite::nebula::cluster_factory_contract());
+    let other_factory = suite
+        .app
+        .instantiate_contract(
+            code_id,
+            suite.cluster_factory.clone(),
+            &suite::nebula::EmptyMsg {},
+            &[],
+            "other factory",
+            None,
+        )
+        .unwrap();
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: None,
+                fee_collector: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: Some(other_factory.to_string()),
+                max_ust_per_mint: None,
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: None,
+            },
+            0,
+        )
+        .unwrap();
+
+    let err = suite.mint(100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::ClusterNotRegistered { .. })
+    ));
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn simulate_mint_rejects_stale_prices() {
+    let mut suite = Suite::new();
+    suite
+        .app
+        .update_block(|block| block.time = block.time.plus_seconds(600));
+
+    let simulate = |suite: &Suite| -> StdResult<SimulateMintResponse> {
+        suite.app.wrap().query_wasm_smart(
+            suite.mint_utils.to_string(),
+            &QueryMsg::SimulateMint {
+                cluster_address: suite.cluster.to_string(),
+                ust_amount: Uint128::new(100_000_000),
+                allocation: None,
+                block_height: None,
+                max_price_age: Some(60),
+                referral_bps: None,
+                in_kind: None,
+            },
+        )
+    };
+    assert!(simulate(&suite).is_err());
+
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked(OWNER),
+            suite.oracle_hub.clone(),
+            &suite::oracle::ExecuteMsg::SetPrice {
+                asset_token: suite.aust_token.to_string(),
+                rate: aust_rate(),
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(simulate(&suite).is_ok());
+}
+
+#[test]
+fn simulate_mint_batch_reports_each_request() {
+    let suite = Suite::new();
+    let response = suite
+        .helper()
+        .simulate_mint_batch(
+            &suite.app.wrap(),
+            vec![
+                (suite.cluster.to_string(), Uint128::new(100_000_000)),
+                ("unknown".to_string(), Uint128::new(100_000_000)),
+            ],
+            None,
+        )
+        .unwrap();
+    assert_eq!(response.results.len(), 2);
+
+    let valid = &response.results[0];
+    assert_eq!(valid.error, None);
+    assert_eq!(valid.simulation, Some(suite.simulate_mint(100_000_000)));
+
+    // an unknown cluster fails on its own without failing the batch
+    let invalid = &response.results[1];
+    assert_eq!(invalid.cluster_address, "unknown");
+    assert_eq!(invalid.simulation, None);
+    assert!(invalid.error.is_some());
+}
+
+#[test]
+fn querier_wraps_cluster_penalty_and_oracle() {
+    let suite = Suite::new();
+    let querier = suite.app.wrap();
+    let cluster = ClusterContract(suite.cluster.clone());
+
+    let config = cluster.config(&querier).unwrap();
+    assert_eq!(config.cluster_token, Some(suite.cluster_token.to_string()));
+    let state = cluster.state(&querier).unwrap();
+    assert_eq!(cluster.target(&querier).unwrap(), state.target);
+
+    let penalty = PenaltyContract(Addr::unchecked(&state.penalty));
+    let create = penalty
+        .create(&querier, 0, &state, state.inv.clone())
+        .unwrap();
+    // providing the whole inventory again doubles the supply
+    assert_eq!(create.create_tokens, state.outstanding_balance_tokens);
+    assert!(penalty.params(&querier).is_ok());
+
+    let price = OracleHub(suite.oracle_hub.clone())
+        .price(&querier, suite.token.to_string(), None)
+        .unwrap();
+    assert_eq!(price.rate, Decimal::from_ratio(TOKEN_PRICE, 1u128));
+}
+
+#[test]
+fn mint_history_records_every_mint() {
+    let mut suite = Suite::new();
+    let helper = suite.helper();
+    suite.mint(100_000_000).unwrap();
+    suite.app.update_block(|block| block.height += 1);
+    suite.mint(50_000_000).unwrap();
+
+    let history = helper
+        .mint_history(&suite.app.wrap(), Some(USER.to_string()), None, None, None)
+        .unwrap();
+    let ids: Vec<u64> = history.mints.iter().map(|mint| mint.id).collect();
+    assert_eq!(ids, vec![1, 0]);
+    assert_eq!(history.mints[0].ust_in, Uint128::new(50_000_000));
+    assert_eq!(history.mints[0].cluster, suite.cluster.to_string());
+    assert_eq!(
+        history.mints[0].block_height,
+        history.mints[1].block_height + 1
+    );
+
+    let page = helper
+        .mint_history(
+            &suite.app.wrap(),
+            None,
+            Some(suite.cluster.to_string()),
+            Some(1),
+            Some(1),
+        )
+        .unwrap();
+    assert_eq!(page.mints, history.mints[1..].to_vec());
+    assert!(helper
+        .mint_history(&suite.app.wrap(), Some(OWNER.to_string()), None, None, None)
+        .unwrap()
+        .mints
+        .is_empty());
+
+    let volume = helper
+        .cluster_volume(&suite.app.wrap(), &suite.cluster)
+        .unwrap();
+    assert_eq!(volume.mints, 2);
+    assert_eq!(volume.ust_in, Uint128::new(150_000_000));
+    assert_eq!(
+        volume.cluster_tokens_out,
+        suite.token_balance(&suite.cluster_token, USER)
+    );
+}
+
+#[test]
+fn internal_messages_only_accept_the_contract() {
+    let mut suite = Suite::new();
+    suite.mint(100_000_000).unwrap();
+    let minted = suite.token_balance(&suite.cluster_token, USER);
+    // a cluster token sent to the contract would be delivered by `_SendToUser`
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked(USER),
+            suite.cluster_token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: suite.mint_utils.to_string(),
+                amount: Uint128::new(1),
+            },
+            &[],
+        )
+        .unwrap();
+
+    let messages = vec![
+        ExecuteMsg::_SendToUser {
+            cluster_address: suite.cluster.to_string(),
+            cluster_token: suite.cluster_token.to_string(),
+            user: USER.to_string(),
+            receipt: MintReceipt {
+                ust_in: Uint128::MAX,
+                ..MintReceipt::default()
+            },
+        },
+        ExecuteMsg::_MintCT {
+            cluster_address: suite.cluster.to_string(),
+            natives: vec!["ukrw".to_string()],
+            tokens: vec![],
+            cluster_token: suite.cluster_token.to_string(),
+            user: USER.to_string(),
+            min_tokens: None,
+            min_assets: None,
+            balances_before: vec![],
+            receipt: MintReceipt::default(),
+        },
+        ExecuteMsg::_MintMany {
+            user: USER.to_string(),
+            swaps: vec![],
+            balances_before: vec![],
+            mints: vec![],
+        },
+        ExecuteMsg::_SwitchSell {
+            from_cluster: suite.cluster.to_string(),
+            to_cluster: suite.cluster.to_string(),
+            user: USER.to_string(),
+            min_out: None,
+        },
+        ExecuteMsg::_SwitchBuy {
+            to_cluster: suite.cluster.to_string(),
+            user: USER.to_string(),
+            min_out: None,
+            deficits: vec![],
+            value: Uint128::zero(),
+        },
+    ];
+    for msg in messages {
+        let err = suite.execute(USER, &msg, 0).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ContractError>(),
+            Some(ContractError::Unauthorized {})
+        ));
+    }
+
+    let volume = suite
+        .helper()
+        .cluster_volume(&suite.app.wrap(), &suite.cluster)
+        .unwrap();
+    assert_eq!(volume.mints, 1);
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        minted - Uint128::new(1)
+    );
+}
+
+#[test]
+fn mint_many_buys_shared_assets_once() {
+    let mut suite = Suite::new();
+    // a second cluster of ukrw and the token, both shared with the first one
+    let (other, other_token) = suite.add_cluster(&[
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+    ]);
+    let helper = suite.helper();
+    let allocations = vec![
+        (suite.cluster.to_string(), Uint128::new(60)),
+        (other.to_string(), Uint128::new(40)),
+    ];
+    let simulation = helper
+        .simulate_mint_many(
+            &suite.app.wrap(),
+            allocations.clone(),
+            Uint128::new(100_000_000),
+            None,
+        )
+        .unwrap();
+    assert_eq!(simulation.mints[0].ust_amount, Uint128::new(60_000_000));
+    assert_eq!(simulation.mints[1].ust_amount, Uint128::new(40_000_000));
+    assert_eq!(simulation.assets.len(), 3);
+
+    let msg = helper
+        .mint_many_msg(
+            allocations,
+            Some(vec![
+                simulation.mints[0].create_tokens,
+                simulation.mints[1].create_tokens,
+            ]),
+            coins(100_000_000, "uusd"),
+            None,
+        )
+        .unwrap();
+    let res = suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+
+    let swaps = res.events.iter().filter(|e| e.ty == "wasm-swap").count();
+    assert_eq!(swaps, 3);
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        simulation.mints[0].create_tokens
+    );
+    assert_eq!(
+        suite.token_balance(&other_token, USER),
+        simulation.mints[1].create_tokens
+    );
+    // every bought asset went into a cluster
+    let mint_utils = suite.mint_utils.to_string();
+    assert_eq!(
+        suite.token_balance(&suite.token, &mint_utils),
+        Uint128::zero()
+    );
+    assert_eq!(
+        suite
+            .app
+            .wrap()
+            .query_balance(&mint_utils, "ukrw")
+            .unwrap()
+            .amount,
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn mint_many_counts_the_whole_deposit_against_the_mint_cap() {
+    let mut suite = Suite::new();
+    let (other, _) = suite.add_cluster(&[
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+    ]);
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: None,
+                fee_collector: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: Some(Uint128::new(80_000_000)),
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: None,
+            },
+            0,
+        )
+        .unwrap();
+
+    // each cluster gets less than the cap, the deposit does not
+    let msg = ExecuteMsg::MintMany {
+        allocations: vec![
+            (suite.cluster.to_string(), Uint128::new(60)),
+            (other.to_string(), Uint128::new(40)),
+        ],
+        min_outputs: None,
+        allocation: None,
+    };
+    let err = suite.execute(USER, &msg, 100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::MintCapExceeded { max }) if *max == Uint128::new(80_000_000)
+    ));
+
+    suite.execute(USER, &msg, 80_000_000).unwrap();
+}
+
+#[test]
+fn mint_many_rejects_duplicate_clusters() {
+    let mut suite = Suite::new();
+    let cluster_address = suite.cluster.to_string();
+    let msg = ExecuteMsg::MintMany {
+        allocations: vec![
+            (cluster_address.clone(), Uint128::new(1)),
+            (cluster_address.clone(), Uint128::new(1)),
+        ],
+        min_outputs: None,
+        allocation: None,
+    };
+
+    let err = suite.execute(USER, &msg, 100_000_000).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ContractError>(),
+        Some(ContractError::DuplicateCluster { cluster }) if *cluster == cluster_address
+    ));
+}
+
+#[test]
+fn mint_many_rejects_other_coins() {
+    let mut suite = Suite::new();
+    suite
+        .app
+        .send_tokens(
+            Addr::unchecked(MARKET),
+            Addr::unchecked(USER),
+            &coins(1_000_000, "ukrw"),
+        )
+        .unwrap();
+    let msg = ExecuteMsg::MintMany {
+        allocations: vec![(suite.cluster.to_string(), Uint128::new(1))],
+        min_outputs: None,
+        allocation: None,
+    };
+
+    let err = suite
+        .app
+        .execute_contract(
+            Addr::unchecked(USER),
+            suite.mint_utils.clone(),
+            &msg,
+            &[coin(100_000_000, "uusd"), coin(1_000_000, "ukrw")],
+        )
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("MintMany only accepts uusd, ukrw sent"));
+}
+
+#[test]
+fn mint_ct_buys_duplicate_target_entries_once() {
+    let mut suite = Suite::new();
+    // the token is listed twice, 25% each
+    let (cluster, cluster_token) = suite.add_cluster(&[
+        (suite.token_info(), 25, 250_000_000 / TOKEN_PRICE),
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 25, 250_000_000 / TOKEN_PRICE),
+    ]);
+    let simulation = suite
+        .helper()
+        .simulate_mint(&suite.app.wrap(), &cluster, Uint128::new(100_000_000))
+        .unwrap();
+    assert_eq!(simulation.assets.len(), 2);
+
+    let msg = ExecuteMsg::MintCT {
+        cluster_address: cluster.to_string(),
+        allocation: None,
+        referrer: None,
+        referral_bps: None,
+        quote: None,
+    };
+    let res = suite.execute(USER, &msg, 100_000_000).unwrap();
+
+    let venues: Vec<&str> = res
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm-swap")
+        .map(|e| attribute(e, "venue"))
+        .collect();
+    assert_eq!(venues, vec!["astroport", "terra_market"]);
+    assert_eq!(
+        suite.token_balance(&cluster_token, USER),
+        simulation.create_tokens
+    );
+    assert_eq!(
+        suite.token_balance(&suite.token, suite.mint_utils.as_str()),
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn mint_ct_swaps_only_the_in_kind_shortfall() {
+    let mut suite = Suite::new();
+    // 24 UST of ukrw is the whole 20% ukrw share of 120 UST
+    let ukrw = Asset {
+        info: suite.ukrw(),
+        amount: Uint128::new(24_000_000 * KRW_PER_UST),
+    };
+    let simulation = suite
+        .helper()
+        .simulate_mint_in_kind(
+            &suite.app.wrap(),
+            &suite.cluster,
+            Uint128::new(96_000_000),
+            vec![ukrw.clone()],
+        )
+        .unwrap();
+    assert_eq!(simulation.create_asset_amounts[0], ukrw.amount);
+    // the ukrw is paid for at its cluster price, about 24 UST
+    let paid = Uint128::new(96_000_000) + ukrw.amount * suite.price(&suite.ukrw());
+    assert_eq!(
+        simulation.effective_price,
+        Decimal::from_ratio(paid, simulation.create_tokens)
+    );
+    assert!(
+        simulation.effective_price > Decimal::from_ratio(96_000_000u128, simulation.create_tokens)
+    );
+
+    let msg = ExecuteMsg::MintCT {
+        cluster_address: suite.cluster.to_string(),
+        allocation: None,
+        referrer: None,
+        referral_bps: None,
+        quote: None,
+    };
+    let res = suite
+        .app
+        .execute_contract(
+            Addr::unchecked(USER),
+            suite.mint_utils.clone(),
+            &msg,
+            &[coin(ukrw.amount.u128(), "ukrw"), coin(96_000_000, "uusd")],
+        )
+        .unwrap();
+
+    let venues: Vec<&str> = res
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm-swap")
+        .map(|e| attribute(e, "venue"))
+        .collect();
+    assert_eq!(venues, vec!["astroport", "anchor"]);
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        simulation.create_tokens
+    );
+    assert_eq!(
+        suite
+            .app
+            .wrap()
+            .query_balance(suite.mint_utils.to_string(), "ukrw")
+            .unwrap()
+            .amount,
+        Uint128::zero()
+    );
+}
+
+#[test]
+fn mint_ct_rejects_in_kind_assets_outside_the_cluster() {
+    let mut suite = Suite::new();
+    suite.mint(100_000_000).unwrap();
+    let amount = suite.token_balance(&suite.cluster_token, USER);
+
+    let msg = suite
+        .helper()
+        .mint_ct_in_kind_msg(
+            &suite.cluster_token,
+            amount,
+            &suite.cluster,
+            MintOptions::default(),
+        )
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err
+        .chain()
+        .any(|cause| cause.to_string().contains("is not in cluster")));
+    assert_eq!(suite.token_balance(&suite.cluster_token, USER), amount);
+}
+
+#[test]
+fn mint_ct_with_a_cw20_in_kind_leaves_the_ust_of_the_contract() {
+    let mut suite = Suite::new();
+    let token = Asset {
+        info: suite.token_info(),
+        amount: Uint128::new(200_000),
+    };
+    suite
+        .app
+        .execute_contract(
+            suite.pair.clone(),
+            suite.token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: USER.to_string(),
+                amount: token.amount,
+            },
+            &[],
+        )
+        .unwrap();
+    // UST left in the contract by someone else
+    let mint_utils = suite.mint_utils.to_string();
+    suite
+        .app
+        .send_tokens(
+            Addr::unchecked(MARKET),
+            suite.mint_utils.clone(),
+            &coins(10_000_000, "uusd"),
+        )
+        .unwrap();
+
+    let helper = suite.helper();
+    let simulation = helper
+        .simulate_mint_in_kind(
+            &suite.app.wrap(),
+            &suite.cluster,
+            Uint128::zero(),
+            vec![token.clone()],
+        )
+        .unwrap();
+    let opts = MintOptions {
+        referrer: Some("referrer".to_string()),
+        quote: Some(MintQuote {
+            create_asset_amounts: simulation.create_asset_amounts.clone(),
+            tolerance: Decimal::percent(1),
+        }),
+        ..MintOptions::default()
+    };
+    let msg = helper
+        .mint_ct_in_kind_msg(&suite.token, token.amount, &suite.cluster, opts)
+        .unwrap();
+    let res = suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+
+    assert!(!res.events.iter().any(|e| e.ty == "wasm-swap"));
+    assert_eq!(
+        suite.token_balance(&suite.cluster_token, USER),
+        simulation.create_tokens
+    );
+    assert_eq!(suite.ust_balance(&mint_utils), Uint128::new(10_000_000));
+    let stats = helper
+        .referral_stats(&suite.app.wrap(), "referrer")
+        .unwrap();
+    assert_eq!(stats.mints, 1);
+    assert_eq!(stats.volume, Uint128::zero());
+}
+
+#[test]
+fn mint_ct_buys_natives_on_the_better_venue() {
+    // the pool pays 1230 ukrw per UST against 1194 on the market, then 1100
+    for (krw_per_ust, venue) in [(1_230u128, "astroport"), (1_100u128, "terra_market")] {
+        let mut suite = Suite::new();
+        let pair = suite.add_native_pair("ukrw", Decimal::from_ratio(1u128, krw_per_ust));
+        let simulation = suite.simulate_mint(100_000_000);
+        let route = if venue == "astroport" {
+            SwapRoute::Astroport {
+                pair_address: pair.to_string(),
+            }
+        } else {
+            SwapRoute::TerraMarket {}
+        };
+        assert_eq!(simulation.assets[0].route, route);
+
+        let res = suite.mint(100_000_000).unwrap();
+        let venues: Vec<&str> = res
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm-swap")
+            .map(|e| attribute(e, "venue"))
+            .collect();
+        assert_eq!(venues, vec![venue, "astroport", "anchor"]);
+        assert_eq!(
+            suite.token_balance(&suite.cluster_token, USER),
+            simulation.create_tokens
+        );
+    }
+}
+
+#[test]
+fn mint_ct_checks_native_pool_purchases_against_the_oracle() {
+    // the pool pays 1230 ukrw per UST, 2.5% above the oracle price of 1200
+    for (max_price_deviation, accepted) in [(2u64, false), (5u64, true)] {
+        let mut suite = Suite::new();
+        suite.add_native_pair("ukrw", Decimal::from_ratio(1u128, 1_230u128));
+        suite
+            .execute(
+                OWNER,
+                &ExecuteMsg::UpdateConfig {
+                    protocol_fee_bps: None,
+                    fee_collector: None,
+                    max_referral_bps: None,
+                    guardian: None,
+                    cluster_factory: None,
+                    max_ust_per_mint: None,
+                    cluster_window_cap: None,
+                    cap_window_seconds: None,
+                    max_price_deviation: Some(Decimal::percent(max_price_deviation)),
+                },
+                0,
+            )
+            .unwrap();
+
+        let res = suite.mint(100_000_000);
+        if accepted {
+            res.unwrap();
+        } else {
+            assert!(matches!(
+                res.unwrap_err().downcast_ref::<ContractError>(),
+                Some(ContractError::PriceDeviation { asset, .. }) if asset == "ukrw"
+            ));
+        }
+    }
+}
+
+#[test]
+fn mint_ct_buys_natives_without_an_oracle_price_on_the_market() {
+    // the pool pays 0.51 ueur per UST against 0.4975 on the market
+    for (max_price_deviation, venue) in [(None, "astroport"), (Some(5u64), "terra_market")] {
+        let mut suite = Suite::new();
+        let (cluster, cluster_token) = suite.add_cluster(&[
+            (suite.ueur(), 50, 500_000_000 / UST_PER_EUR),
+            (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+        ]);
+        suite.cluster = cluster;
+        suite.cluster_token = cluster_token;
+        suite.add_native_pair("ueur", Decimal::from_ratio(196u128, 100u128));
+        suite
+            .execute(
+                OWNER,
+                &ExecuteMsg::UpdateConfig {
+                    protocol_fee_bps: None,
+                    fee_collector: None,
+                    max_referral_bps: None,
+                    guardian: None,
+                    cluster_factory: None,
+                    max_ust_per_mint: None,
+                    cluster_window_cap: None,
+                    cap_window_seconds: None,
+                    max_price_deviation: max_price_deviation.map(Decimal::percent),
+                },
+                0,
+            )
+            .unwrap();
+
+        let simulation = suite.simulate_mint(100_000_000);
+        let res = suite.mint(100_000_000).unwrap();
+        let venues: Vec<&str> = res
+            .events
+            .iter()
+            .filter(|e| e.ty == "wasm-swap")
+            .map(|e| attribute(e, "venue"))
+            .collect();
+        assert_eq!(venues[0], venue);
+        assert_eq!(
+            simulation.assets[0].route == SwapRoute::TerraMarket {},
+            venue == "terra_market"
+        );
+        assert_eq!(
+            suite.token_balance(&suite.cluster_token, USER),
+            simulation.create_tokens
+        );
+    }
+}
+
+#[test]
+fn switch_cluster_trades_only_the_basket_difference() {
+    let mut suite = Suite::new();
+    // a cluster of ukrw and the token, without aUST
+    let (other, other_token) = suite.add_cluster(&[
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+    ]);
+    suite.mint(200_000_000).unwrap();
+    let amount = suite
+        .token_balance(&suite.cluster_token, USER)
+        .multiply_ratio(1u128, 2u128);
+
+    let helper = suite.helper();
+    let simulation = helper
+        .simulate_switch_cluster(&suite.app.wrap(), &suite.cluster, &other, amount)
+        .unwrap();
+    let aust = simulation
+        .redeem_assets
+        .iter()
+        .find(|asset| asset.info == suite.aust_info())
+        .unwrap()
+        .clone();
+    assert!(simulation.sold.contains(&aust));
+    assert!(!simulation
+        .sold
+        .iter()
+        .any(|asset| asset.info == suite.ukrw()));
+    assert!(!simulation.create_tokens.is_zero());
+
+    let msg = helper
+        .switch_cluster_msg(
+            &suite.cluster_token,
+            amount,
+            &other,
+            Some(simulation.create_tokens),
+        )
+        .unwrap();
+    let res = suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+
+    let sells: Vec<&str> = res
+        .events
+        .iter()
+        .filter(|e| e.ty == "wasm-sell")
+        .map(|e| attribute(e, "asset"))
+        .collect();
+    assert!(sells.contains(&suite.aust_token.as_str()));
+    assert!(!sells.contains(&"ukrw"));
+    assert_eq!(
+        suite.token_balance(&other_token, USER),
+        simulation.create_tokens
+    );
+    // nothing is left behind in the contract
+    let mint_utils = suite.mint_utils.to_string();
+    assert_eq!(
+        suite.token_balance(&suite.aust_token, &mint_utils),
+        Uint128::zero()
+    );
+    assert_eq!(
+        suite.token_balance(&suite.token, &mint_utils),
+        Uint128::zero()
+    );
+    assert_eq!(suite.ust_balance(&mint_utils), Uint128::zero());
+}
+
+#[test]
+fn switch_cluster_pays_the_protocol_fee_within_the_mint_caps() {
+    let mut suite = Suite::new();
+    let (other, other_token) = suite.add_cluster(&[
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+    ]);
+    suite.mint(200_000_000).unwrap();
+    let amount = suite
+        .token_balance(&suite.cluster_token, USER)
+        .multiply_ratio(1u128, 2u128);
+    let config = |protocol_fee_bps, max_ust_per_mint| ExecuteMsg::UpdateConfig {
+        protocol_fee_bps,
+        fee_collector: Some("collector".to_string()),
+        max_referral_bps: None,
+        guardian: None,
+        cluster_factory: None,
+        max_ust_per_mint,
+        cluster_window_cap: None,
+        cap_window_seconds: None,
+        max_price_deviation: None,
+    };
+    let helper = suite.helper();
+
+    // the value switched is a mint into the new cluster
+    suite
+        .execute(OWNER, &config(None, Some(Uint128::new(1_000_000))), 0)
+        .unwrap();
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &other, None)
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err
+        .chain()
+        .any(|cause| cause.to_string().contains("exceeds the maximum")));
+
+    suite
+        .execute(OWNER, &config(Some(30), Some(Uint128::zero())), 0)
+        .unwrap();
+    let simulation = helper
+        .simulate_switch_cluster(&suite.app.wrap(), &suite.cluster, &other, amount)
+        .unwrap();
+    // the fee is charged on the UST of the sales, not on the value kept in kind
+    assert!(!simulation.protocol_fee.is_zero());
+    assert_eq!(
+        simulation.protocol_fee,
+        simulation.ust_amount.multiply_ratio(30u128, 10_000u128)
+    );
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &other, None)
+        .unwrap();
+    suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+    assert_eq!(suite.ust_balance("collector"), simulation.protocol_fee);
+    assert_eq!(
+        suite.token_balance(&other_token, USER),
+        simulation.create_tokens
+    );
+}
+
+#[test]
+fn switch_cluster_between_identical_baskets_charges_only_the_sales() {
+    let mut suite = Suite::new();
+    let (other, other_token) = suite.add_cluster(&[
+        (suite.ukrw(), 20, 200_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+        (suite.aust_info(), 30, 250_000_000),
+    ]);
+    suite.mint(200_000_000).unwrap();
+    let amount = suite
+        .token_balance(&suite.cluster_token, USER)
+        .multiply_ratio(1u128, 2u128);
+    suite
+        .execute(
+            OWNER,
+            &ExecuteMsg::UpdateConfig {
+                protocol_fee_bps: Some(30),
+                fee_collector: Some("collector".to_string()),
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: None,
+                max_ust_per_mint: None,
+                cluster_window_cap: None,
+                cap_window_seconds: None,
+                max_price_deviation: None,
+            },
+            0,
+        )
+        .unwrap();
+    let helper = suite.helper();
+
+    let simulation = helper
+        .simulate_switch_cluster(&suite.app.wrap(), &suite.cluster, &other, amount)
+        .unwrap();
+    // only the drift of the basket from the target is sold, and only that UST pays the fee
+    let value = simulation
+        .redeem_assets
+        .iter()
+        .fold(Uint128::zero(), |total, asset| {
+            total + asset.amount * suite.price(&asset.info)
+        });
+    assert!(simulation.ust_amount < value.multiply_ratio(1u128, 1_000u128));
+    assert_eq!(
+        simulation.protocol_fee,
+        simulation.ust_amount.multiply_ratio(30u128, 10_000u128)
+    );
+
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &other, None)
+        .unwrap();
+    suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+    assert_eq!(suite.ust_balance("collector"), simulation.protocol_fee);
+    assert_eq!(
+        suite.token_balance(&other_token, USER),
+        simulation.create_tokens
+    );
+
+    // the whole value switched is recorded as the mint into the new cluster
+    let volume = helper.cluster_volume(&suite.app.wrap(), &other).unwrap();
+    assert_eq!(volume.mints, 1);
+    assert_eq!(volume.ust_in, value);
+}
+
+#[test]
+fn switch_cluster_follows_the_allowlist() {
+    let mut suite = Suite::new();
+    let (other, other_token) = suite.add_cluster(&[
+        (suite.ukrw(), 50, 500_000_000 * KRW_PER_UST),
+        (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+    ]);
+    suite.mint(200_000_000).unwrap();
+    let amount = suite
+        .token_balance(&suite.cluster_token, USER)
+        .multiply_ratio(1u128, 2u128);
+    let cluster = |cluster_address: &Addr, allowed: bool| {
+        let cluster_address = cluster_address.to_string();
+        if allowed {
+            ExecuteMsg::AddCluster { cluster_address }
+        } else {
+            ExecuteMsg::RemoveCluster { cluster_address }
+        }
+    };
+
+    // the token of a removed cluster is no longer recognized
+    suite
+        .execute(OWNER, &cluster(&suite.cluster.clone(), false), 0)
+        .unwrap();
+    let helper = suite.helper();
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &other, None)
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err.chain().any(|cause| cause
+        .to_string()
+        .contains("not the token of an allowed cluster")));
+
+    suite
+        .execute(OWNER, &cluster(&suite.cluster.clone(), true), 0)
+        .unwrap();
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &other, None)
+        .unwrap();
+    suite.app.execute(Addr::unchecked(USER), msg).unwrap();
+    assert!(!suite.token_balance(&other_token, USER).is_zero());
+}
+
+#[test]
+fn switch_cluster_rejects_the_same_cluster() {
+    let mut suite = Suite::new();
+    suite.mint(100_000_000).unwrap();
+    let amount = suite.token_balance(&suite.cluster_token, USER);
+
+    let helper = suite.helper();
+    assert!(helper
+        .simulate_switch_cluster(&suite.app.wrap(), &suite.cluster, &suite.cluster, amount)
+        .is_err());
+
+    let msg = helper
+        .switch_cluster_msg(&suite.cluster_token, amount, &suite.cluster, None)
+        .unwrap();
+    let err = suite.app.execute(Addr::unchecked(USER), msg).unwrap_err();
+    assert!(err
+        .chain()
+        .any(|cause| cause.to_string().contains("into itself")));
+    assert_eq!(suite.token_balance(&suite.cluster_token, USER), amount);
+}
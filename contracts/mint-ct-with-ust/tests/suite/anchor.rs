@@ -0,0 +1,117 @@
+use cosmwasm_std::{
+    coin, from_binary, to_binary, Addr, BankMsg, Binary, Decimal, Deps, DepsMut, Env, MessageInfo,
+    Response, StdError, StdResult, Uint128, WasmMsg,
+};
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_multi_test::{Contract, ContractWrapper};
+use cw_storage_plus::Item;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use mint_ct_with_ust::msg::AnchorCw20HookMsg;
+use terra_cosmwasm::TerraMsgWrapper;
+
+const DECIMAL_FRACTIONAL: Uint128 = Uint128::new(1_000_000_000u128);
+
+/// ## Description
+/// An Anchor market paying deposits in aUST and redemptions in UST from its own balances
+/// at a fixed exchange rate.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub aust_token: Addr,
+    /// UST paid per aUST
+    pub exchange_rate: Decimal,
+}
+
+const CONFIG: Item<Config> = Item::new("config");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InstantiateMsg {
+    pub aust_token: String,
+    pub exchange_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    DepositStable {},
+    /// Redeems the aUST sent for UST
+    Receive(Cw20ReceiveMsg),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+}
+
+fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: InstantiateMsg,
+) -> StdResult<Response> {
+    CONFIG.save(
+        deps.storage,
+        &Config {
+            aust_token: deps.api.addr_validate(&msg.aust_token)?,
+            exchange_rate: msg.exchange_rate,
+        },
+    )?;
+
+    Ok(Response::new())
+}
+
+fn execute(deps: DepsMut, _env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+    match msg {
+        ExecuteMsg::DepositStable {} => {
+            let config = CONFIG.load(deps.storage)?;
+            let deposit = info
+                .funds
+                .iter()
+                .find(|c| c.denom == "uusd")
+                .map(|c| c.amount)
+                .filter(|amount| !amount.is_zero())
+                .ok_or_else(|| StdError::generic_err("Deposit amount must be positive"))?;
+
+            Ok(Response::new().add_message(WasmMsg::Execute {
+                contract_addr: config.aust_token.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: info.sender.to_string(),
+                    amount: deposit.multiply_ratio(
+                        DECIMAL_FRACTIONAL,
+                        config.exchange_rate * DECIMAL_FRACTIONAL,
+                    ),
+                })?,
+                funds: vec![],
+            }))
+        }
+        ExecuteMsg::Receive(cw20_msg) => {
+            let config = CONFIG.load(deps.storage)?;
+            if info.sender != config.aust_token {
+                return Err(StdError::generic_err("Market only accepts aUST"));
+            }
+            match from_binary(&cw20_msg.msg)? {
+                AnchorCw20HookMsg::RedeemStable {} => {
+                    Ok(Response::new().add_message(BankMsg::Send {
+                        to_address: cw20_msg.sender,
+                        amount: vec![coin(
+                            (cw20_msg.amount * config.exchange_rate).u128(),
+                            "uusd",
+                        )],
+                    }))
+                }
+            }
+        }
+    }
+}
+
+fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&CONFIG.load(deps.storage)?),
+    }
+}
+
+pub fn contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(execute, instantiate, query))
+}
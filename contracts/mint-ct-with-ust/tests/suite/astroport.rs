@@ -0,0 +1,272 @@
+use cosmwasm_std::{
+    coin, from_binary, to_binary, Addr, BankMsg, Binary, Decimal, Deps, DepsMut, Env, MessageInfo,
+    Response, StdError, StdResult, Uint128, WasmMsg,
+};
+use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
+use cw_multi_test::{Contract, ContractWrapper};
+use cw_storage_plus::{Item, Map};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use astroport::asset::{Asset, AssetInfo, PairInfo};
+use astroport::factory::PairType;
+use astroport::pair::{Cw20HookMsg, SimulationResponse};
+use terra_cosmwasm::TerraMsgWrapper;
+
+const DECIMAL_FRACTIONAL: Uint128 = Uint128::new(1_000_000_000u128);
+
+//////////////////////////////////////////////////////////////////////
+// FACTORY
+//////////////////////////////////////////////////////////////////////
+
+/// Pairs against UST, keyed by the other asset
+const PAIRS: Map<&str, Addr> = Map::new("pairs");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FactoryInstantiateMsg {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryExecuteMsg {
+    /// Registers the UST pair of an asset
+    RegisterPair { asset_info: AssetInfo, pair: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FactoryQueryMsg {
+    Pair { asset_infos: [AssetInfo; 2] },
+}
+
+fn factory_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: FactoryInstantiateMsg,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn factory_execute(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: FactoryExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        FactoryExecuteMsg::RegisterPair { asset_info, pair } => {
+            let pair = deps.api.addr_validate(&pair)?;
+            PAIRS.save(deps.storage, &asset_info.to_string(), &pair)?;
+            Ok(Response::new())
+        }
+    }
+}
+
+fn factory_query(deps: Deps, _env: Env, msg: FactoryQueryMsg) -> StdResult<Binary> {
+    match msg {
+        FactoryQueryMsg::Pair { asset_infos } => {
+            let asset_info = asset_infos
+                .iter()
+                .find(|info| info.to_string() != "uusd")
+                .ok_or_else(|| StdError::generic_err("Pair needs an asset other than uusd"))?;
+            let contract_addr = PAIRS.load(deps.storage, &asset_info.to_string())?;
+
+            to_binary(&PairInfo {
+                asset_infos,
+                contract_addr,
+                liquidity_token: Addr::unchecked(""),
+                pair_type: PairType::Xyk {},
+            })
+        }
+    }
+}
+
+pub fn factory_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        factory_execute,
+        factory_instantiate,
+        factory_query,
+    ))
+}
+
+//////////////////////////////////////////////////////////////////////
+// PAIR
+//////////////////////////////////////////////////////////////////////
+
+/// ## Description
+/// A UST pair filling swaps at a fixed price from its own balances of the asset and UST.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairConfig {
+    /// A cw20 token or a native denom other than UST
+    pub asset: AssetInfo,
+    /// UST paid per unit of the asset
+    pub price: Decimal,
+    /// Share of the returned asset kept by the pair
+    pub commission_rate: Decimal,
+}
+
+const PAIR_CONFIG: Item<PairConfig> = Item::new("config");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PairInstantiateMsg {
+    pub asset: AssetInfo,
+    pub price: Decimal,
+    pub commission_rate: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PairExecuteMsg {
+    Swap {
+        offer_asset: Asset,
+        belief_price: Option<Decimal>,
+        max_spread: Option<Decimal>,
+        to: Option<String>,
+    },
+    /// Sells the token sent for UST
+    Receive(Cw20ReceiveMsg),
+    /// Moves the pool price, callable by anyone
+    SetPrice { price: Decimal },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PairQueryMsg {
+    Simulation { offer_asset: Asset },
+}
+
+fn pair_instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: PairInstantiateMsg,
+) -> StdResult<Response> {
+    PAIR_CONFIG.save(
+        deps.storage,
+        &PairConfig {
+            asset: msg.asset,
+            price: msg.price,
+            commission_rate: msg.commission_rate,
+        },
+    )?;
+
+    Ok(Response::new())
+}
+
+fn pair_execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: PairExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        PairExecuteMsg::Swap {
+            offer_asset, to, ..
+        } => {
+            let config = PAIR_CONFIG.load(deps.storage)?;
+            let sent = info
+                .funds
+                .iter()
+                .find(|c| c.denom == "uusd")
+                .map(|c| c.amount)
+                .unwrap_or_default();
+            if sent != offer_asset.amount {
+                return Err(StdError::generic_err(
+                    "Offer amount does not match the funds",
+                ));
+            }
+
+            let simulation = simulate(&config, &offer_asset)?;
+            let recipient = to.unwrap_or_else(|| info.sender.to_string());
+            match config.asset {
+                AssetInfo::Token { contract_addr } => {
+                    Ok(Response::new().add_message(WasmMsg::Execute {
+                        contract_addr: contract_addr.to_string(),
+                        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                            recipient,
+                            amount: simulation.return_amount,
+                        })?,
+                        funds: vec![],
+                    }))
+                }
+                AssetInfo::NativeToken { denom } => {
+                    Ok(Response::new().add_message(BankMsg::Send {
+                        to_address: recipient,
+                        amount: vec![coin(simulation.return_amount.u128(), denom)],
+                    }))
+                }
+            }
+        }
+        PairExecuteMsg::Receive(cw20_msg) => {
+            let config = PAIR_CONFIG.load(deps.storage)?;
+            let token = AssetInfo::Token {
+                contract_addr: info.sender,
+            };
+            if token != config.asset {
+                return Err(StdError::generic_err("Pair only accepts its token"));
+            }
+            let to = match from_binary(&cw20_msg.msg)? {
+                Cw20HookMsg::Swap { to, .. } => to,
+                Cw20HookMsg::WithdrawLiquidity {} => {
+                    return Err(StdError::generic_err("Pair has no liquidity"))
+                }
+            };
+
+            let simulation = simulate(
+                &config,
+                &Asset {
+                    info: token,
+                    amount: cw20_msg.amount,
+                },
+            )?;
+            Ok(Response::new().add_message(BankMsg::Send {
+                to_address: to.unwrap_or(cw20_msg.sender),
+                amount: vec![coin(simulation.return_amount.u128(), "uusd")],
+            }))
+        }
+        PairExecuteMsg::SetPrice { price } => {
+            PAIR_CONFIG.update(deps.storage, |mut config| -> StdResult<PairConfig> {
+                config.price = price;
+                Ok(config)
+            })?;
+            Ok(Response::new())
+        }
+    }
+}
+
+fn simulate(config: &PairConfig, offer_asset: &Asset) -> StdResult<SimulationResponse> {
+    let amount = if offer_asset.info.to_string() == "uusd" {
+        offer_asset
+            .amount
+            .multiply_ratio(DECIMAL_FRACTIONAL, config.price * DECIMAL_FRACTIONAL)
+    } else if offer_asset.info == config.asset {
+        offer_asset.amount * config.price
+    } else {
+        return Err(StdError::generic_err(
+            "Pair only accepts uusd and its asset",
+        ));
+    };
+    let commission_amount = amount * config.commission_rate;
+
+    Ok(SimulationResponse {
+        return_amount: amount - commission_amount,
+        spread_amount: Uint128::zero(),
+        commission_amount,
+    })
+}
+
+fn pair_query(deps: Deps, _env: Env, msg: PairQueryMsg) -> StdResult<Binary> {
+    match msg {
+        PairQueryMsg::Simulation { offer_asset } => {
+            to_binary(&simulate(&PAIR_CONFIG.load(deps.storage)?, &offer_asset)?)
+        }
+    }
+}
+
+pub fn pair_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        pair_execute,
+        pair_instantiate,
+        pair_query,
+    ))
+}
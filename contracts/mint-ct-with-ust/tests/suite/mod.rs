@@ -0,0 +1,532 @@
+//! Multi-test harness running the contract against mock Nebula, Astroport, Anchor and
+//! oracle hub contracts, with `TerraModule` standing in for the Terra market.
+
+pub mod anchor;
+pub mod astroport;
+pub mod nebula;
+pub mod oracle;
+pub mod terra;
+
+use anyhow::Result as AnyResult;
+use cosmwasm_std::{
+    coin, coins, Addr, BankMsg, Coin, Decimal, DepsMut, Env, MessageInfo, Response, Uint128,
+};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg, MinterResponse};
+use cw_multi_test::{AppBuilder, AppResponse, Contract, ContractWrapper, Executor};
+use serde::Serialize;
+
+use ::astroport::asset::{Asset, AssetInfo};
+use mint_ct_with_ust::contract;
+use mint_ct_with_ust::helpers::MintUtilsContract;
+use mint_ct_with_ust::msg::{ExecuteMsg, InstantiateMsg, SimulateMintResponse};
+use mint_ct_with_ust::ContractError;
+use terra_cosmwasm::TerraMsgWrapper;
+
+use self::terra::{TerraApp, TerraModule};
+
+pub const OWNER: &str = "owner";
+pub const USER: &str = "user";
+/// Account paying out the Terra market swaps
+pub const MARKET: &str = "market";
+
+/// Amount of ukrw received per uusd on the Terra market
+pub const KRW_PER_UST: u128 = 1_200;
+/// UST price of the Astroport token
+pub const TOKEN_PRICE: u128 = 150;
+/// UST paid per ueur on the Terra market, a coin the oracle hub has no price for
+pub const UST_PER_EUR: u128 = 2;
+
+/// ## Description
+/// Returns the UST paid per aUST.
+pub fn aust_rate() -> Decimal {
+    Decimal::permille(1_200)
+}
+
+/// ## Description
+/// A chain holding the contract and a cluster of ukrw, an Astroport token and aUST,
+/// with every dependency of the contract deployed as a mock.
+pub struct Suite {
+    pub app: TerraApp,
+    pub mint_utils: Addr,
+    pub cluster: Addr,
+    pub cluster_token: Addr,
+    pub cluster_factory: Addr,
+    /// Token bought through its Astroport pair
+    pub token: Addr,
+    pub pair: Addr,
+    pub aust_token: Addr,
+    pub oracle_hub: Addr,
+    astroport_factory: Addr,
+    cw20_code: u64,
+    penalty: Addr,
+    incentives: Addr,
+}
+
+/// ## Description
+/// Adapts the instantiate entry point to the Terra messages returned by `execute`.
+fn instantiate_terra(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response<TerraMsgWrapper>, ContractError> {
+    let response = contract::instantiate(deps, env, info, msg)?;
+
+    Ok(Response::new().add_attributes(response.attributes))
+}
+
+pub fn mint_utils_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new(
+        contract::execute,
+        instantiate_terra,
+        contract::query,
+    ))
+}
+
+pub fn cw20_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    ))
+}
+
+impl Default for Suite {
+    fn default() -> Self {
+        Suite::new()
+    }
+}
+
+impl Suite {
+    pub fn new() -> Self {
+        let owner = Addr::unchecked(OWNER);
+        let mut app: TerraApp = AppBuilder::new()
+            .with_custom(TerraModule {
+                market: Addr::unchecked(MARKET),
+                exchange_rates: vec![
+                    ("ukrw".to_string(), Decimal::from_ratio(KRW_PER_UST, 1u128)),
+                    ("ueur".to_string(), Decimal::from_ratio(1u128, UST_PER_EUR)),
+                ],
+                spread: Decimal::permille(5),
+            })
+            .build();
+        app.init_bank_balance(
+            &Addr::unchecked(USER),
+            vec![
+                coin(1_000_000_000_000, "ukrw"),
+                coin(1_000_000_000_000, "uusd"),
+            ],
+        )
+        .unwrap();
+        app.init_bank_balance(
+            &Addr::unchecked(MARKET),
+            vec![
+                coin(1_000_000_000_000_000, "ukrw"),
+                coin(1_000_000_000_000_000, "ueur"),
+                coin(1_000_000_000_000, "uusd"),
+            ],
+        )
+        .unwrap();
+        app.init_bank_balance(&owner, coins(2_000_000_000_000, "uusd"))
+            .unwrap();
+
+        let cw20_code = app.store_code(cw20_contract());
+        let token = instantiate_cw20(&mut app, cw20_code, "mAAPL", None);
+        let aust_token = instantiate_cw20(&mut app, cw20_code, "aUST", None);
+
+        let astroport_factory = instantiate_mock(
+            &mut app,
+            astroport::factory_contract(),
+            &astroport::FactoryInstantiateMsg {},
+        );
+        let pair = instantiate_pair(
+            &mut app,
+            &astroport_factory,
+            AssetInfo::Token {
+                contract_addr: token.clone(),
+            },
+            Decimal::from_ratio(TOKEN_PRICE, 1u128),
+        );
+        fund_cw20(&mut app, &token, &pair);
+        fund_ust(&mut app, &pair);
+
+        let anchor_market = instantiate_mock(
+            &mut app,
+            anchor::contract(),
+            &anchor::InstantiateMsg {
+                aust_token: aust_token.to_string(),
+                exchange_rate: aust_rate(),
+            },
+        );
+        fund_cw20(&mut app, &aust_token, &anchor_market);
+        fund_ust(&mut app, &anchor_market);
+
+        let oracle_hub = instantiate_mock(
+            &mut app,
+            oracle::contract(),
+            &oracle::InstantiateMsg {
+                prices: vec![
+                    ("ukrw".to_string(), Decimal::from_ratio(1u128, KRW_PER_UST)),
+                    (token.to_string(), Decimal::from_ratio(TOKEN_PRICE, 1u128)),
+                    (aust_token.to_string(), aust_rate()),
+                ],
+            },
+        );
+
+        let penalty = instantiate_mock(&mut app, nebula::penalty_contract(), &nebula::EmptyMsg {});
+        let incentives = instantiate_mock(
+            &mut app,
+            nebula::incentives_contract(),
+            &nebula::EmptyMsg {},
+        );
+        let cluster_factory = instantiate_mock(
+            &mut app,
+            nebula::cluster_factory_contract(),
+            &nebula::EmptyMsg {},
+        );
+
+        let mint_utils = instantiate_mock(
+            &mut app,
+            mint_utils_contract(),
+            &InstantiateMsg {
+                incentive_contract: incentives.to_string(),
+                astroport_factory_address: astroport_factory.to_string(),
+                aust_token_address: aust_token.to_string(),
+                anchor_market_contract: anchor_market.to_string(),
+                oracle_hub_contract: oracle_hub.to_string(),
+                owner_address: OWNER.to_string(),
+                fee_collector: None,
+                protocol_fee_bps: None,
+                max_referral_bps: None,
+                guardian: None,
+                cluster_factory: Some(cluster_factory.to_string()),
+                max_price_deviation: None,
+            },
+        );
+
+        let mut suite = Suite {
+            app,
+            mint_utils,
+            cluster: Addr::unchecked(""),
+            cluster_token: Addr::unchecked(""),
+            cluster_factory,
+            token,
+            pair,
+            aust_token,
+            oracle_hub,
+            astroport_factory,
+            cw20_code,
+            penalty,
+            incentives,
+        };
+
+        // a cluster worth 1000 UST, 20% ukrw, 50% token and 30% aUST, with 1000 tokens
+        let (cluster, cluster_token) = suite.add_cluster(&[
+            (suite.ukrw(), 20, 200_000_000 * KRW_PER_UST),
+            (suite.token_info(), 50, 500_000_000 / TOKEN_PRICE),
+            (suite.aust_info(), 30, 250_000_000),
+        ]);
+        suite.cluster = cluster;
+        suite.cluster_token = cluster_token;
+
+        suite
+    }
+
+    pub fn ukrw(&self) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: "ukrw".to_string(),
+        }
+    }
+
+    pub fn ueur(&self) -> AssetInfo {
+        AssetInfo::NativeToken {
+            denom: "ueur".to_string(),
+        }
+    }
+
+    pub fn token_info(&self) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: self.token.clone(),
+        }
+    }
+
+    pub fn aust_info(&self) -> AssetInfo {
+        AssetInfo::Token {
+            contract_addr: self.aust_token.clone(),
+        }
+    }
+
+    /// ## Description
+    /// Returns the UST price of an asset of the suite.
+    pub fn price(&self, info: &AssetInfo) -> Decimal {
+        if *info == self.ukrw() {
+            Decimal::from_ratio(1u128, KRW_PER_UST)
+        } else if *info == self.ueur() {
+            Decimal::from_ratio(UST_PER_EUR, 1u128)
+        } else if *info == self.token_info() {
+            Decimal::from_ratio(TOKEN_PRICE, 1u128)
+        } else {
+            aust_rate()
+        }
+    }
+
+    /// ## Description
+    /// Deploys an allowlisted cluster with 1000 tokens outstanding from its assets, each
+    /// with its target weight and inventory. Returns the cluster and its token.
+    pub fn add_cluster(&mut self, assets: &[(AssetInfo, u128, u128)]) -> (Addr, Addr) {
+        let owner = Addr::unchecked(OWNER);
+        let cluster_token = instantiate_cw20(
+            &mut self.app,
+            self.cw20_code,
+            "CLUSTER",
+            Some(self.incentives.to_string()),
+        );
+
+        let prices = assets
+            .iter()
+            .map(|(info, _, _)| self.price(info).to_string())
+            .collect();
+        let cluster = instantiate_mock(
+            &mut self.app,
+            nebula::cluster_contract(),
+            &nebula::ClusterInstantiateMsg {
+                name: "Test cluster".to_string(),
+                cluster_token: cluster_token.to_string(),
+                factory: self.cluster_factory.to_string(),
+                penalty: self.penalty.to_string(),
+                target: assets
+                    .iter()
+                    .map(|(info, weight, _)| Asset {
+                        info: info.clone(),
+                        amount: Uint128::new(*weight),
+                    })
+                    .collect(),
+                prices,
+                inv: assets
+                    .iter()
+                    .map(|(_, _, inv)| Uint128::new(*inv))
+                    .collect(),
+                outstanding_balance_tokens: Uint128::new(1_000_000_000),
+            },
+        );
+
+        self.app
+            .execute_contract(
+                owner.clone(),
+                self.cluster_factory.clone(),
+                &nebula::ClusterFactoryExecuteMsg::RegisterCluster {
+                    contract_addr: cluster.to_string(),
+                },
+                &[],
+            )
+            .unwrap();
+        self.app
+            .execute_contract(
+                owner,
+                self.mint_utils.clone(),
+                &ExecuteMsg::AddCluster {
+                    cluster_address: cluster.to_string(),
+                },
+                &[],
+            )
+            .unwrap();
+
+        (cluster, cluster_token)
+    }
+
+    /// ## Description
+    /// Lists a UST pair of the native `denom` on Astroport, paying `price` UST per unit of
+    /// `denom` out of a reserve taken from the market.
+    pub fn add_native_pair(&mut self, denom: &str, price: Decimal) -> Addr {
+        let pair = instantiate_pair(
+            &mut self.app,
+            &self.astroport_factory,
+            AssetInfo::NativeToken {
+                denom: denom.to_string(),
+            },
+            price,
+        );
+        self.app
+            .execute(
+                Addr::unchecked(MARKET),
+                BankMsg::Send {
+                    to_address: pair.to_string(),
+                    amount: coins(100_000_000_000_000, denom),
+                }
+                .into(),
+            )
+            .unwrap();
+
+        pair
+    }
+
+    /// ## Description
+    /// Executes `msg` on the contract from `sender`, sending `ust_amount` uusd.
+    pub fn execute(
+        &mut self,
+        sender: &str,
+        msg: &ExecuteMsg,
+        ust_amount: u128,
+    ) -> AnyResult<AppResponse> {
+        let funds: Vec<Coin> = if ust_amount == 0 {
+            vec![]
+        } else {
+            coins(ust_amount, "uusd")
+        };
+
+        self.app.execute_contract(
+            Addr::unchecked(sender),
+            self.mint_utils.clone(),
+            msg,
+            &funds,
+        )
+    }
+
+    /// ## Description
+    /// Mints the cluster token with `ust_amount` uusd from the user.
+    pub fn mint(&mut self, ust_amount: u128) -> AnyResult<AppResponse> {
+        let msg = ExecuteMsg::MintCT {
+            cluster_address: self.cluster.to_string(),
+            allocation: None,
+            referrer: None,
+            referral_bps: None,
+            quote: None,
+        };
+
+        self.execute(USER, &msg, ust_amount)
+    }
+
+    pub fn simulate_mint(&self, ust_amount: u128) -> SimulateMintResponse {
+        self.helper()
+            .simulate_mint(&self.app.wrap(), &self.cluster, Uint128::new(ust_amount))
+            .unwrap()
+    }
+
+    pub fn helper(&self) -> MintUtilsContract {
+        MintUtilsContract(self.mint_utils.clone())
+    }
+
+    pub fn ust_balance(&self, address: &str) -> Uint128 {
+        self.app
+            .wrap()
+            .query_balance(address, "uusd")
+            .unwrap()
+            .amount
+    }
+
+    pub fn token_balance(&self, token: &Addr, address: &str) -> Uint128 {
+        let response: BalanceResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(
+                token.to_string(),
+                &Cw20QueryMsg::Balance {
+                    address: address.to_string(),
+                },
+            )
+            .unwrap();
+
+        response.balance
+    }
+}
+
+fn instantiate_mock<T: Serialize>(
+    app: &mut TerraApp,
+    contract: Box<dyn Contract<TerraMsgWrapper>>,
+    msg: &T,
+) -> Addr {
+    let code_id = app.store_code(contract);
+
+    app.instantiate_contract(code_id, Addr::unchecked(OWNER), msg, &[], "mock", None)
+        .unwrap()
+}
+
+/// ## Description
+/// Instantiates an Astroport pair of `asset_info` against UST and registers it with the factory.
+fn instantiate_pair(
+    app: &mut TerraApp,
+    astroport_factory: &Addr,
+    asset_info: AssetInfo,
+    price: Decimal,
+) -> Addr {
+    let pair = instantiate_mock(
+        app,
+        astroport::pair_contract(),
+        &astroport::PairInstantiateMsg {
+            asset: asset_info.clone(),
+            price,
+            commission_rate: Decimal::permille(3),
+        },
+    );
+    app.execute_contract(
+        Addr::unchecked(OWNER),
+        astroport_factory.clone(),
+        &astroport::FactoryExecuteMsg::RegisterPair {
+            asset_info,
+            pair: pair.to_string(),
+        },
+        &[],
+    )
+    .unwrap();
+
+    pair
+}
+
+/// ## Description
+/// Instantiates a cw20 token with the owner holding its whole initial supply.
+fn instantiate_cw20(
+    app: &mut TerraApp,
+    code_id: u64,
+    symbol: &str,
+    minter: Option<String>,
+) -> Addr {
+    app.instantiate_contract(
+        code_id,
+        Addr::unchecked(OWNER),
+        &cw20_base::msg::InstantiateMsg {
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            initial_balances: vec![Cw20Coin {
+                address: OWNER.to_string(),
+                amount: Uint128::new(1_000_000_000_000),
+            }],
+            mint: minter.map(|minter| MinterResponse { minter, cap: None }),
+            marketing: None,
+        },
+        &[],
+        symbol,
+        None,
+    )
+    .unwrap()
+}
+
+/// ## Description
+/// Transfers the whole supply of the owner to `recipient`, the reserve of a mock venue.
+fn fund_cw20(app: &mut TerraApp, token: &Addr, recipient: &Addr) {
+    app.execute_contract(
+        Addr::unchecked(OWNER),
+        token.clone(),
+        &Cw20ExecuteMsg::Transfer {
+            recipient: recipient.to_string(),
+            amount: Uint128::new(1_000_000_000_000),
+        },
+        &[],
+    )
+    .unwrap();
+}
+
+/// ## Description
+/// Sends half of the UST of the owner to `recipient`, the reserve a mock venue pays
+/// sales out of.
+fn fund_ust(app: &mut TerraApp, recipient: &Addr) {
+    app.execute(
+        Addr::unchecked(OWNER),
+        BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: coins(1_000_000_000_000, "uusd"),
+        }
+        .into(),
+    )
+    .unwrap();
+}
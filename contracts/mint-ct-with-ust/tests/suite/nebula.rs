@@ -0,0 +1,486 @@
+use std::str::FromStr;
+
+use cosmwasm_std::{
+    attr, coin, to_binary, Addr, BankMsg, Binary, CosmosMsg, Decimal, Deps, DepsMut, Env,
+    MessageInfo, Response, StdError, StdResult, Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::{Contract, ContractWrapper};
+use cw_storage_plus::{Item, Map};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use astroport::asset::{Asset, AssetInfo};
+use mint_ct_with_ust::msg::{
+    ClusterConfig, ClusterConfigResponse, ClusterExistsResponse, ClusterStateResponse,
+    ClusterTargetResponse, IncentivesMsg, PenaltyCreateResponse, PenaltyParams,
+    PenaltyParamsResponse, QueryMsgClusterFactory, QueryMsgNebula, QueryMsgPenalty,
+};
+use mint_ct_with_ust::querier::{ClusterContract, PenaltyContract};
+use terra_cosmwasm::TerraMsgWrapper;
+
+//////////////////////////////////////////////////////////////////////
+// CLUSTER
+//////////////////////////////////////////////////////////////////////
+
+/// ## Description
+/// A cluster holding a fixed basket. Its inventory and supply only move through `RecordCreate`
+/// and `RecordRedeem`, the latter paying out of the assets the cluster actually holds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ClusterInstantiateMsg {
+    pub name: String,
+    pub cluster_token: String,
+    pub factory: String,
+    pub penalty: String,
+    pub target: Vec<Asset>,
+    /// UST price of each target asset
+    pub prices: Vec<String>,
+    pub inv: Vec<Uint128>,
+    pub outstanding_balance_tokens: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterExecuteMsg {
+    /// Adds a create to the inventory and supply, callable by anyone
+    RecordCreate {
+        create_asset_amounts: Vec<Uint128>,
+        create_tokens: Uint128,
+    },
+    /// Removes a redeem from the inventory and supply and sends the assets to `recipient`,
+    /// callable by anyone
+    RecordRedeem {
+        redeem_asset_amounts: Vec<Uint128>,
+        redeem_tokens: Uint128,
+        recipient: String,
+    },
+}
+
+const CLUSTER_CONFIG: Item<ClusterConfig> = Item::new("config");
+const CLUSTER_STATE: Item<ClusterStateResponse> = Item::new("state");
+
+fn cluster_instantiate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ClusterInstantiateMsg,
+) -> StdResult<Response> {
+    CLUSTER_CONFIG.save(
+        deps.storage,
+        &ClusterConfig {
+            name: msg.name.clone(),
+            description: msg.name,
+            owner: info.sender.to_string(),
+            cluster_token: Some(msg.cluster_token.clone()),
+            factory: msg.factory,
+            pricing_oracle: String::new(),
+            target_oracle: String::new(),
+            penalty: msg.penalty.clone(),
+            active: true,
+        },
+    )?;
+    CLUSTER_STATE.save(
+        deps.storage,
+        &ClusterStateResponse {
+            outstanding_balance_tokens: msg.outstanding_balance_tokens,
+            prices: msg.prices,
+            inv: msg.inv,
+            penalty: msg.penalty,
+            cluster_token: msg.cluster_token,
+            target: msg.target,
+            cluster_contract_address: env.contract.address.to_string(),
+            active: true,
+        },
+    )?;
+
+    Ok(Response::new())
+}
+
+fn cluster_execute(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: ClusterExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        ClusterExecuteMsg::RecordCreate {
+            create_asset_amounts,
+            create_tokens,
+        } => {
+            CLUSTER_STATE.update(deps.storage, |mut state| -> StdResult<_> {
+                for (inv, amount) in state.inv.iter_mut().zip(create_asset_amounts) {
+                    *inv += amount;
+                }
+                state.outstanding_balance_tokens += create_tokens;
+                Ok(state)
+            })?;
+            Ok(Response::new())
+        }
+        ClusterExecuteMsg::RecordRedeem {
+            redeem_asset_amounts,
+            redeem_tokens,
+            recipient,
+        } => {
+            let state = CLUSTER_STATE.update(deps.storage, |mut state| -> StdResult<_> {
+                for (inv, amount) in state.inv.iter_mut().zip(redeem_asset_amounts.iter()) {
+                    *inv = inv.checked_sub(*amount)?;
+                }
+                state.outstanding_balance_tokens = state
+                    .outstanding_balance_tokens
+                    .checked_sub(redeem_tokens)?;
+                Ok(state)
+            })?;
+
+            let mut msgs: Vec<CosmosMsg> = vec![];
+            for (asset, amount) in state.target.iter().zip(redeem_asset_amounts) {
+                if amount.is_zero() {
+                    continue;
+                }
+                msgs.push(match &asset.info {
+                    AssetInfo::NativeToken { denom } => CosmosMsg::Bank(BankMsg::Send {
+                        to_address: recipient.clone(),
+                        amount: vec![coin(amount.u128(), denom.clone())],
+                    }),
+                    AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+                        contract_addr: contract_addr.to_string(),
+                        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                            recipient: recipient.clone(),
+                            amount,
+                        })?,
+                        funds: vec![],
+                    }),
+                });
+            }
+
+            Ok(Response::new().add_messages(msgs))
+        }
+    }
+}
+
+fn cluster_query(deps: Deps, _env: Env, msg: QueryMsgNebula) -> StdResult<Binary> {
+    match msg {
+        QueryMsgNebula::Config {} => to_binary(&ClusterConfigResponse {
+            config: CLUSTER_CONFIG.load(deps.storage)?,
+        }),
+        QueryMsgNebula::ClusterState {} => to_binary(&CLUSTER_STATE.load(deps.storage)?),
+        QueryMsgNebula::Target {} => to_binary(&ClusterTargetResponse {
+            target: CLUSTER_STATE.load(deps.storage)?.target,
+        }),
+    }
+}
+
+pub fn cluster_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        cluster_execute,
+        cluster_instantiate,
+        cluster_query,
+    ))
+}
+
+//////////////////////////////////////////////////////////////////////
+// PENALTY
+//////////////////////////////////////////////////////////////////////
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct EmptyMsg {}
+
+fn penalty_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: EmptyMsg,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn penalty_execute(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: EmptyMsg,
+) -> StdResult<Response> {
+    Err(StdError::generic_err("Penalty has no execute messages"))
+}
+
+/// Returns the UST value of the given amounts of the cluster assets.
+fn basket_value(amounts: &[Uint128], prices: &[String]) -> StdResult<Uint128> {
+    amounts
+        .iter()
+        .zip(prices.iter())
+        .map(|(amount, price)| Ok(*amount * Decimal::from_str(price)?))
+        .sum()
+}
+
+/// ## Description
+/// Mints cluster tokens at the net asset value of the cluster, without any penalty.
+fn penalty_query(_deps: Deps, _env: Env, msg: QueryMsgPenalty) -> StdResult<Binary> {
+    match msg {
+        QueryMsgPenalty::PenaltyQueryCreate {
+            cluster_token_supply,
+            inventory,
+            create_asset_amounts,
+            asset_prices,
+            ..
+        } => {
+            let create_value = basket_value(&create_asset_amounts, &asset_prices)?;
+            let inventory_value = basket_value(&inventory, &asset_prices)?;
+            let create_tokens = if cluster_token_supply.is_zero() || inventory_value.is_zero() {
+                create_value
+            } else {
+                cluster_token_supply.multiply_ratio(create_value, inventory_value)
+            };
+
+            to_binary(&PenaltyCreateResponse {
+                create_tokens,
+                penalty: Uint128::zero(),
+                attributes: vec![attr("penalty", "0")],
+            })
+        }
+        QueryMsgPenalty::Params {} => to_binary(&PenaltyParamsResponse {
+            penalty_params: PenaltyParams {
+                penalty_amt_lo: Decimal::zero(),
+                penalty_cutoff_lo: Decimal::zero(),
+                penalty_amt_hi: Decimal::zero(),
+                penalty_cutoff_hi: Decimal::zero(),
+                reward_amt: Decimal::zero(),
+                reward_cutoff: Decimal::zero(),
+            },
+            ema: Decimal::one(),
+            last_block: 0,
+        }),
+    }
+}
+
+pub fn penalty_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        penalty_execute,
+        penalty_instantiate,
+        penalty_query,
+    ))
+}
+
+//////////////////////////////////////////////////////////////////////
+// INCENTIVES
+//////////////////////////////////////////////////////////////////////
+
+fn incentives_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: EmptyMsg,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+/// ## Description
+/// Moves the offered assets into the cluster and mints the cluster tokens quoted by its
+/// penalty contract to the sender. The incentives contract must be the cluster token minter.
+/// Redeems burn the cluster tokens of the sender for a pro rata share of the inventory.
+fn incentives_execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: IncentivesMsg,
+) -> StdResult<Response> {
+    match msg {
+        IncentivesMsg::IncentivesCreate {
+            cluster_contract,
+            asset_amounts,
+            min_tokens,
+        } => {
+            let cluster = deps.api.addr_validate(&cluster_contract)?;
+            let state = ClusterContract(cluster.clone()).state(&deps.querier)?;
+
+            // an asset listed twice in the target is provided to its first entry
+            let create_asset_amounts: Vec<Uint128> = state
+                .target
+                .iter()
+                .enumerate()
+                .map(|(index, target)| {
+                    if state.target[..index]
+                        .iter()
+                        .any(|other| other.info == target.info)
+                    {
+                        return Uint128::zero();
+                    }
+                    asset_amounts
+                        .iter()
+                        .find(|asset| asset.info == target.info)
+                        .map(|asset| asset.amount)
+                        .unwrap_or_default()
+                })
+                .collect();
+            let penalty = PenaltyContract(deps.api.addr_validate(&state.penalty)?).create(
+                &deps.querier,
+                env.block.height,
+                &state,
+                create_asset_amounts.clone(),
+            )?;
+            if let Some(min_tokens) = min_tokens {
+                if penalty.create_tokens < min_tokens {
+                    return Err(StdError::generic_err(format!(
+                        "Created tokens {} below the minimum {}",
+                        penalty.create_tokens, min_tokens
+                    )));
+                }
+            }
+
+            let mut msgs: Vec<CosmosMsg> = vec![];
+            for asset in asset_amounts.iter() {
+                if let AssetInfo::Token { contract_addr } = &asset.info {
+                    if !asset.amount.is_zero() {
+                        msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                            contract_addr: contract_addr.to_string(),
+                            msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
+                                owner: info.sender.to_string(),
+                                recipient: cluster.to_string(),
+                                amount: asset.amount,
+                            })?,
+                            funds: vec![],
+                        }));
+                    }
+                }
+            }
+            if !info.funds.is_empty() {
+                msgs.push(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: cluster.to_string(),
+                    amount: info.funds.clone(),
+                }));
+            }
+            msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: state.cluster_token,
+                msg: to_binary(&Cw20ExecuteMsg::Mint {
+                    recipient: info.sender.to_string(),
+                    amount: penalty.create_tokens,
+                })?,
+                funds: vec![],
+            }));
+            msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: cluster.to_string(),
+                msg: to_binary(&ClusterExecuteMsg::RecordCreate {
+                    create_asset_amounts,
+                    create_tokens: penalty.create_tokens,
+                })?,
+                funds: vec![],
+            }));
+
+            Ok(Response::new()
+                .add_messages(msgs)
+                .add_attribute("mint_to_sender", penalty.create_tokens))
+        }
+        IncentivesMsg::IncentivesRedeem {
+            cluster_contract,
+            max_tokens,
+            asset_amounts,
+        } => {
+            if asset_amounts.is_some() {
+                return Err(StdError::generic_err("Only pro rata redeems are supported"));
+            }
+            let cluster = deps.api.addr_validate(&cluster_contract)?;
+            let state = ClusterContract(cluster.clone()).state(&deps.querier)?;
+
+            let redeem_asset_amounts: Vec<Uint128> = state
+                .inv
+                .iter()
+                .map(|inv| inv.multiply_ratio(max_tokens, state.outstanding_balance_tokens))
+                .collect();
+
+            Ok(Response::new()
+                .add_message(WasmMsg::Execute {
+                    contract_addr: state.cluster_token.clone(),
+                    msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
+                        owner: info.sender.to_string(),
+                        recipient: env.contract.address.to_string(),
+                        amount: max_tokens,
+                    })?,
+                    funds: vec![],
+                })
+                .add_message(WasmMsg::Execute {
+                    contract_addr: state.cluster_token,
+                    msg: to_binary(&Cw20ExecuteMsg::Burn { amount: max_tokens })?,
+                    funds: vec![],
+                })
+                .add_message(WasmMsg::Execute {
+                    contract_addr: cluster.to_string(),
+                    msg: to_binary(&ClusterExecuteMsg::RecordRedeem {
+                        redeem_asset_amounts,
+                        redeem_tokens: max_tokens,
+                        recipient: info.sender.to_string(),
+                    })?,
+                    funds: vec![],
+                })
+                .add_attribute("burn_amount", max_tokens))
+        }
+    }
+}
+
+fn incentives_query(_deps: Deps, _env: Env, _msg: EmptyMsg) -> StdResult<Binary> {
+    Err(StdError::generic_err("Incentives has no queries"))
+}
+
+pub fn incentives_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        incentives_execute,
+        incentives_instantiate,
+        incentives_query,
+    ))
+}
+
+//////////////////////////////////////////////////////////////////////
+// CLUSTER FACTORY
+//////////////////////////////////////////////////////////////////////
+
+/// Clusters created by the factory
+const CLUSTERS: Map<&Addr, bool> = Map::new("clusters");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClusterFactoryExecuteMsg {
+    /// Registers a cluster, callable by anyone
+    RegisterCluster { contract_addr: String },
+}
+
+fn cluster_factory_instantiate(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: EmptyMsg,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn cluster_factory_execute(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: ClusterFactoryExecuteMsg,
+) -> StdResult<Response> {
+    match msg {
+        ClusterFactoryExecuteMsg::RegisterCluster { contract_addr } => {
+            CLUSTERS.save(
+                deps.storage,
+                &deps.api.addr_validate(&contract_addr)?,
+                &true,
+            )?;
+            Ok(Response::new())
+        }
+    }
+}
+
+fn cluster_factory_query(deps: Deps, _env: Env, msg: QueryMsgClusterFactory) -> StdResult<Binary> {
+    match msg {
+        QueryMsgClusterFactory::ClusterExists { contract_addr } => {
+            to_binary(&ClusterExistsResponse {
+                exists: CLUSTERS.has(deps.storage, &deps.api.addr_validate(&contract_addr)?),
+            })
+        }
+    }
+}
+
+pub fn cluster_factory_contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(
+        cluster_factory_execute,
+        cluster_factory_instantiate,
+        cluster_factory_query,
+    ))
+}
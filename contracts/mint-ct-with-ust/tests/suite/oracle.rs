@@ -0,0 +1,81 @@
+use cosmwasm_std::{
+    to_binary, Binary, Decimal, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult,
+};
+use cw_multi_test::{Contract, ContractWrapper};
+use cw_storage_plus::Map;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use mint_ct_with_ust::msg::{PriceResponse, QueryMsgOracleHub};
+use terra_cosmwasm::TerraMsgWrapper;
+
+/// Price and time of the last update, keyed by asset
+const PRICES: Map<&str, (Decimal, u64)> = Map::new("prices");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InstantiateMsg {
+    /// UST price of each asset
+    pub prices: Vec<(String, Decimal)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    /// Updates the price of an asset at the current block time, callable by anyone
+    SetPrice { asset_token: String, rate: Decimal },
+}
+
+fn instantiate(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    msg: InstantiateMsg,
+) -> StdResult<Response> {
+    for (asset_token, rate) in msg.prices {
+        PRICES.save(
+            deps.storage,
+            &asset_token,
+            &(rate, env.block.time.seconds()),
+        )?;
+    }
+
+    Ok(Response::new())
+}
+
+fn execute(deps: DepsMut, env: Env, _info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+    match msg {
+        ExecuteMsg::SetPrice { asset_token, rate } => {
+            PRICES.save(
+                deps.storage,
+                &asset_token,
+                &(rate, env.block.time.seconds()),
+            )?;
+            Ok(Response::new())
+        }
+    }
+}
+
+fn query(deps: Deps, env: Env, msg: QueryMsgOracleHub) -> StdResult<Binary> {
+    match msg {
+        QueryMsgOracleHub::Price {
+            asset_token,
+            timeframe,
+        } => {
+            let (rate, last_updated) = PRICES.load(deps.storage, &asset_token)?;
+            if let Some(timeframe) = timeframe {
+                if env.block.time.seconds().saturating_sub(last_updated) > timeframe {
+                    return Err(StdError::generic_err(format!(
+                        "No price of {} within the timeframe",
+                        asset_token
+                    )));
+                }
+            }
+
+            to_binary(&PriceResponse { rate, last_updated })
+        }
+    }
+}
+
+pub fn contract() -> Box<dyn Contract<TerraMsgWrapper>> {
+    Box::new(ContractWrapper::new_with_empty(execute, instantiate, query))
+}
@@ -0,0 +1,139 @@
+use anyhow::{bail, Result as AnyResult};
+use cosmwasm_std::{
+    coin, to_binary, Addr, Api, BankMsg, Binary, BlockInfo, Coin, Decimal, Storage, Uint128,
+};
+use cw_multi_test::{App, AppResponse, Bank, BankKeeper, CustomHandler};
+
+use terra_cosmwasm::{
+    ExchangeRateItem, ExchangeRatesResponse, SwapResponse, TerraMsg, TerraMsgWrapper, TerraQuery,
+    TerraQueryWrapper,
+};
+
+const DECIMAL_FRACTIONAL: Uint128 = Uint128::new(1_000_000_000u128);
+
+pub type TerraApp = App<TerraMsgWrapper, TerraQueryWrapper>;
+
+/// ## Description
+/// A stand-in for the Terra market module. Swaps are filled at fixed exchange rates from
+/// the balance of the `market` account, less the market spread.
+pub struct TerraModule {
+    /// Account holding the native coins paid out by swaps
+    pub market: Addr,
+    /// Amount of each denom received per uusd
+    pub exchange_rates: Vec<(String, Decimal)>,
+    /// Spread taken by the market on every swap
+    pub spread: Decimal,
+}
+
+impl TerraModule {
+    fn exchange_rate(&self, denom: &str) -> AnyResult<Decimal> {
+        match self.exchange_rates.iter().find(|(d, _)| d == denom) {
+            Some((_, rate)) => Ok(*rate),
+            None => bail!("No exchange rate for {}", denom),
+        }
+    }
+
+    /// ## Description
+    /// Returns the coin received when swapping `offer_coin` into `ask_denom`.
+    /// One side of the swap must be uusd.
+    pub fn swap(&self, offer_coin: &Coin, ask_denom: &str) -> AnyResult<Coin> {
+        let gross = if offer_coin.denom == "uusd" {
+            offer_coin.amount * self.exchange_rate(ask_denom)?
+        } else if ask_denom == "uusd" {
+            let rate = self.exchange_rate(&offer_coin.denom)?;
+            offer_coin
+                .amount
+                .multiply_ratio(DECIMAL_FRACTIONAL, rate * DECIMAL_FRACTIONAL)
+        } else {
+            bail!("Terra module only swaps from or into uusd");
+        };
+        let received = gross - gross * self.spread;
+
+        Ok(coin(received.u128(), ask_denom))
+    }
+}
+
+impl CustomHandler<TerraMsgWrapper, TerraQueryWrapper> for TerraModule {
+    fn execute(
+        &self,
+        _api: &dyn Api,
+        storage: &mut dyn Storage,
+        _block: &BlockInfo,
+        sender: Addr,
+        msg: TerraMsgWrapper,
+    ) -> AnyResult<AppResponse> {
+        let (to_address, offer_coin, ask_denom) = match msg.msg_data {
+            TerraMsg::Swap {
+                offer_coin,
+                ask_denom,
+            } => (sender.clone(), offer_coin, ask_denom),
+            TerraMsg::SwapSend {
+                to_address,
+                offer_coin,
+                ask_denom,
+            } => (Addr::unchecked(to_address), offer_coin, ask_denom),
+        };
+        let receive = self.swap(&offer_coin, &ask_denom)?;
+
+        // the handler is given no router, so the coins move through the bank directly
+        let bank = BankKeeper::new();
+        bank.execute(
+            storage,
+            sender,
+            BankMsg::Send {
+                to_address: self.market.to_string(),
+                amount: vec![offer_coin],
+            },
+        )?;
+        bank.execute(
+            storage,
+            self.market.clone(),
+            BankMsg::Send {
+                to_address: to_address.to_string(),
+                amount: vec![receive],
+            },
+        )?;
+
+        Ok(AppResponse::default())
+    }
+
+    fn query(
+        &self,
+        _api: &dyn Api,
+        _storage: &dyn Storage,
+        _block: &BlockInfo,
+        request: TerraQueryWrapper,
+    ) -> AnyResult<Binary> {
+        match request.query_data {
+            TerraQuery::Swap {
+                offer_coin,
+                ask_denom,
+            } => Ok(to_binary(&SwapResponse {
+                receive: self.swap(&offer_coin, &ask_denom)?,
+            })?),
+            TerraQuery::ExchangeRates {
+                base_denom,
+                quote_denoms,
+            } => {
+                if base_denom != "uusd" {
+                    bail!("Terra module only quotes against uusd");
+                }
+                let exchange_rates = quote_denoms
+                    .into_iter()
+                    .map(|quote_denom| {
+                        Ok(ExchangeRateItem {
+                            exchange_rate: self.exchange_rate(&quote_denom)?,
+                            quote_denom,
+                        })
+                    })
+                    .collect::<AnyResult<Vec<ExchangeRateItem>>>()?;
+
+                Ok(to_binary(&ExchangeRatesResponse {
+                    base_denom,
+                    exchange_rates,
+                })?)
+            }
+            query => bail!("Unsupported Terra query {:?}", query),
+        }
+    }
+}